@@ -1,4 +1,4 @@
-use log::{debug, trace};
+use log::{debug, info, trace, warn};
 use winit::{
     event::{DeviceEvent, ElementState, KeyEvent, WindowEvent},
     keyboard::{Key, NamedKey},
@@ -6,30 +6,110 @@ use winit::{
 };
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
-use crate::renderer::{cached::CachedMesh, light::LightUniform, Renderer};
+use crate::renderer::{
+    cached::CachedChunk,
+    mesh::{MeshStats, Mesher},
+    post::PostEffect,
+    CullStats, Renderer,
+};
 
 mod block;
 mod chunk;
+mod clock;
+mod light;
 mod mesh;
+mod pack;
 mod player;
-mod voxel;
+mod screenshot;
+pub(crate) mod voxel;
 mod worldgen;
 
-use chunk::{Chunk, ChunkState, CHUNK_SIZE};
-use mesh::BgMesher;
+use block::Block;
+use chunk::{locate, Chunk, ChunkState, CHUNK_SIZE};
+pub use clock::Clock;
+use mesh::{BgMesher, ChunkRegion, Culling, MeshPolicy, MissingNeighbors};
+use pack::{Changes, ResourcePack};
 use player::Player;
 
+/// Where the resource pack is loaded from.
+const RESOURCE_PACK: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+/// Where the built-in shaders are read from in debug builds, so that they
+/// can be edited without rebuilding.
+const SHADER_SOURCE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// Where screenshots are saved, relative to the working directory.
+const SCREENSHOTS: &str = "screenshots";
+/// How many times the width and height of the window posters are.
+const POSTER_SCALE: u32 = 4;
+
+/// How far away anything can be seen, in blocks.
+const VIEW_DISTANCE: f32 = 160.0;
+/// How far past the view distance chunks keep their meshes, so that they
+/// aren't freed and remeshed as the player moves back and forth.
+const EVICT_MARGIN: f32 = 32.0;
+
+/// How a world's terrain is meshed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    /// Every block is drawn as a cube.
+    #[default]
+    Blocky,
+    /// Solid blocks are drawn as one smooth surface.
+    Smooth,
+}
+
+impl Terrain {
+    /// The mesher chunks are first meshed with.
+    fn mesher(self) -> Mesher {
+        match self {
+            Terrain::Blocky => Mesher::Fast,
+            Terrain::Smooth => Mesher::Smooth,
+        }
+    }
+}
+
+/// Generate and light a world of `size` chunks from `seed`, caching every
+/// chunk's mesh in `renderer` along with the blocks' palette.
+///
+/// This is the world [`ApplicationState`] starts with, without a window or
+/// anything loaded from the resource pack, e.g. for drawing offscreen.
+pub fn mesh_world(
+    renderer: &mut Renderer,
+    seed: i32,
+    size: [usize; 3],
+    terrain: Terrain,
+) -> Vec<CachedChunk> {
+    renderer.palette(&block::palette());
+
+    let mut chunks = worldgen::gen(seed, [0, 0, 0], size);
+    light::light_all(&mut chunks);
+
+    chunks
+        .keys()
+        .map(|&pos| {
+            let region = ChunkRegion::new(pos, &chunks, Culling::default());
+            renderer.cache(mesh::mesh(terrain.mesher(), pos, &region, true, false))
+        })
+        .collect()
+}
+
 pub struct ApplicationState {
     pub renderer: Renderer,
     pub exit: bool,
     pub changed: bool,
 
     chunks: HashMap<[i32; 3], Chunk>,
-    chunk_cache: HashMap<[i32; 3], CachedMesh>,
+    chunk_cache: HashMap<[i32; 3], CachedChunk>,
     bg_mesher: BgMesher,
+    culling: Culling,
+    policy: MeshPolicy,
+    terrain: Terrain,
+    clock: Clock,
+    pack: ResourcePack,
 
     player: Player,
 }
@@ -49,29 +129,56 @@ impl ApplicationState {
             [x, y, z + 1],
         ] {
             if let Some(chunk) = self.chunks.get_mut(&chunk) {
-                chunk.state = ChunkState::Remesh;
+                if chunk.state != ChunkState::Evicted {
+                    chunk.state = ChunkState::Remesh;
+                }
             }
         }
     }
 
     /// Insert a chunk into the world.
     ///
-    /// Queues the chunk for meshing, as well as its neighbors.
+    /// Queues the chunk for meshing, as well as its neighbors and any chunks
+    /// whose light it changes.
     pub fn insert_chunk(&mut self, at: [i32; 3], mut chunk: Chunk) {
         chunk.state = ChunkState::Remesh;
-        self.chunks.insert(at, chunk);
-        self.flag(at);
+        for pos in light::insert_chunk(&mut self.chunks, at, chunk) {
+            self.flag(pos);
+        }
+        self.changed = true;
+    }
+
+    /// Get the block at a world-space position, if it's loaded.
+    pub fn block(&self, pos: [i32; 3]) -> Option<Block> {
+        let (chunk, [x, y, z]) = locate(pos);
+        self.chunks.get(&chunk).map(|chunk| chunk.blocks[y][z][x])
+    }
+
+    /// Place a block at a world-space position, relighting the area around
+    /// it.
+    ///
+    /// Queues every chunk whose blocks or light changed for meshing, along
+    /// with their neighbors.
+    pub fn set_block(&mut self, pos: [i32; 3], block: Block) {
+        for chunk in light::set_block(&mut self.chunks, pos, block) {
+            self.flag(chunk);
+            self.changed = true;
+        }
     }
 
     pub async fn new(window: &'static Window) -> Self {
-        let sun = LightUniform::new([200.0, 200.0, 200.0], [1.0, 1.0, 1.0]);
+        let clock = Clock::default();
         let mut renderer = Renderer::new(window).await;
-        renderer.light(sun);
+        renderer.light(clock.light());
+        renderer.set_sky(clock.sky());
+        renderer.set_view_distance(VIEW_DISTANCE);
+        renderer.palette(&block::palette());
 
-        let chunks = worldgen::gen(0, [0, 0, 0], [5, 2, 5]);
+        let mut chunks = worldgen::gen(0, [0, 0, 0], [5, 2, 5]);
+        light::light_all(&mut chunks);
 
         let size = renderer.size;
-        Self {
+        let mut state = Self {
             renderer,
             exit: false,
             changed: true,
@@ -79,21 +186,145 @@ impl ApplicationState {
             chunks,
             chunk_cache: HashMap::new(),
             bg_mesher: BgMesher::new(),
+            culling: Culling::default(),
+            policy: MeshPolicy::new(),
+            terrain: Terrain::default(),
+            clock,
+            pack: ResourcePack::new(
+                RESOURCE_PACK,
+                cfg!(debug_assertions).then(|| SHADER_SOURCE.into()),
+            ),
+
+            player: Player::new(size),
+        };
+        state.reload(Changes::ALL);
+        state
+    }
 
-            player: Player::new([0.0, 2.0, 0.0], size),
+    /// Draw a frame `scale` times the width and height of the window, and
+    /// save it as a PNG, returning where it went.
+    pub fn screenshot(&mut self, scale: u32) -> anyhow::Result<PathBuf> {
+        let image = self
+            .renderer
+            .render_poster(self.chunk_cache.values(), scale)?;
+        screenshot::save(&image, Path::new(SCREENSHOTS))
+    }
+
+    fn save_screenshot(&mut self, scale: u32) {
+        match self.screenshot(scale) {
+            Ok(path) => info!("Saved screenshot to {path:?}"),
+            Err(err) => warn!("Failed to save screenshot: {err:#}"),
         }
     }
 
+    /// Load whatever changed in the resource pack, keeping what was loaded
+    /// before of anything that fails to load.
+    ///
+    /// Meshes only refer to blocks' looks by palette index, so none of this
+    /// needs any chunks remeshed. How brightly blocks glow isn't part of the
+    /// pack, since it follows the light they give off, which chunks are lit
+    /// by.
+    fn reload(&mut self, changes: Changes) {
+        if changes.shaders {
+            let shaders = self.pack.shaders().and_then(|mut shaders| {
+                shaders.features = self.renderer.shaders().features.clone();
+                self.renderer.set_shaders(shaders)
+            });
+            if let Err(err) = shaders {
+                warn!("Failed to load shaders: {err:#}");
+            }
+        }
+        if changes.textures {
+            if let Err(err) = self.renderer.load_textures(self.pack.textures()) {
+                warn!("Failed to load textures: {err:#}");
+            }
+        }
+        if changes.color_grading {
+            let lut = self.pack.color_grading();
+            if let Err(err) = lut.and_then(|lut| self.renderer.set_color_grading(lut.as_ref())) {
+                warn!("Failed to load color grading table: {err:#}");
+            }
+        }
+        if changes.blocks {
+            match self.pack.palette() {
+                Ok(palette) => self.renderer.palette(&palette),
+                Err(err) => warn!("Failed to load block definitions: {err:#}"),
+            }
+        }
+    }
+
+    /// Change the rules for culling faces, remeshing every chunk.
+    pub fn set_culling(&mut self, culling: Culling) {
+        self.culling = culling;
+        for chunk in self.chunks.values_mut() {
+            if chunk.state != ChunkState::Evicted {
+                chunk.state = ChunkState::Remesh;
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Change how the world's terrain is meshed, remeshing every chunk.
+    pub fn set_terrain(&mut self, terrain: Terrain) {
+        self.terrain = terrain;
+        for chunk in self.chunks.values_mut() {
+            if chunk.state != ChunkState::Evicted {
+                chunk.state = ChunkState::Remesh;
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Get the statistics of each chunk's current mesh.
+    pub fn mesh_stats(&self) -> impl Iterator<Item = ([i32; 3], &MeshStats)> {
+        self.chunk_cache
+            .iter()
+            .map(|(&pos, cached)| (pos, cached.stats()))
+    }
+
+    /// Get the GPU memory used by each chunk's mesh, in bytes.
+    pub fn mesh_memory(&self) -> impl Iterator<Item = ([i32; 3], u64)> + '_ {
+        self.chunk_cache
+            .iter()
+            .map(|(&pos, cached)| (pos, self.renderer.chunk_usage(cached)))
+    }
+
+    /// Get the total GPU memory used by chunk meshes, and the size of the
+    /// buffers holding them, in bytes.
+    pub fn total_mesh_memory(&self) -> (u64, u64) {
+        self.renderer.cache_usage()
+    }
+
+    /// Get statistics about which chunks were culled in the last frame.
+    pub fn cull_stats(&self) -> CullStats {
+        self.renderer.cull_stats()
+    }
+
+    /// Get the policy deciding which chunks get greedy meshed.
+    pub fn mesh_policy(&mut self) -> &mut MeshPolicy {
+        &mut self.policy
+    }
+
     pub fn draw(&mut self) {
         if let Some((pos, mesh)) = self.bg_mesher.query() {
-            let old = self.chunk_cache.get_mut(&pos).unwrap();
-            self.renderer.update_cache(old, mesh);
-            self.chunks.get_mut(&pos).unwrap().state = ChunkState::Cached;
+            // the chunk may have changed since it was sent off
+            if self.chunks[&pos].state == ChunkState::Greedy {
+                let old = self.chunk_cache.get_mut(&pos).unwrap();
+                let kinds = self.chunks[&pos].kinds();
+                self.policy.record(old.stats(), mesh.stats(), kinds);
+                trace!("Greedy meshed chunk {pos:?}: {:?}", mesh.stats());
+                self.renderer.update_cache(old, mesh);
+                let chunk = self.chunks.get_mut(&pos).unwrap();
+                chunk.state = ChunkState::Cached;
+                chunk.stale_lods = false;
+            }
         }
 
         if self.changed {
             let start = std::time::Instant::now();
 
+            let eye = self.player.camera.eye.into_array();
+            let lod_distance = self.renderer.lod_distance();
             let mut updated = vec![None; self.chunks.len()];
             let mut updated_chunks = updated.chunks_mut(1).map(|c| &mut c[0]);
 
@@ -104,29 +335,43 @@ impl ApplicationState {
                     match chunk.state {
                         ChunkState::Remesh => {
                             let chunks = &self.chunks;
+                            let culling = self.culling;
+                            let mesher = self.terrain.mesher();
+                            // nearby, the lower levels of detail aren't seen,
+                            // so the cached ones do until they're next needed
+                            let lods = match self.chunk_cache.get(&pos) {
+                                Some(cached) => {
+                                    let smooth = cached.stats().mesher == Mesher::Smooth;
+                                    smooth != (mesher == Mesher::Smooth)
+                                        || chunk::center_distance(pos, eye) >= lod_distance
+                                }
+                                None => true,
+                            };
                             s.spawn(move || {
-                                let mesh = mesh::fast(chunk, pos, chunks);
+                                let region = ChunkRegion::new(pos, chunks, culling);
+                                let mesh = mesh::mesh(mesher, pos, &region, lods, false);
                                 *out = Some((pos, mesh));
                             });
                         }
                         ChunkState::Greedy => {
-                            if self.bg_mesher.mesh(pos, &self.chunks) {
+                            if self.bg_mesher.mesh(pos, &self.chunks, self.culling) {
                                 trace!("Greedy meshing chunk {pos:?}");
                             }
                         }
-                        ChunkState::Cached => {}
+                        ChunkState::Cached | ChunkState::Evicted => {}
                     }
                 }
             });
 
             let mut num_updated = 0;
             for (pos, mesh) in updated.into_iter().flatten() {
-                if mesh.vertices.len() < 15000 {
-                    // TODO: keep tabs on this number
-                    self.chunks.get_mut(&pos).unwrap().state = ChunkState::Greedy;
+                let chunk = self.chunks.get_mut(&pos).unwrap();
+                chunk.state = if self.policy.prefer_greedy(mesh.stats(), chunk.kinds()) {
+                    ChunkState::Greedy
                 } else {
-                    self.chunks.get_mut(&pos).unwrap().state = ChunkState::Cached;
-                }
+                    ChunkState::Cached
+                };
+                chunk.stale_lods = mesh.lods.is_empty();
 
                 if let Some(old) = self.chunk_cache.get_mut(&pos) {
                     self.renderer.update_cache(old, mesh);
@@ -145,13 +390,16 @@ impl ApplicationState {
                 (end - start).as_secs_f64()
             );
 
+            let (used, capacity) = self.renderer.cache_usage();
+            trace!("Chunk arena: {used} of {capacity} bytes used");
+
             self.changed = false;
         } else if let Some((pos, _)) = self
             .chunks
             .iter()
             .find(|(_, c)| c.state == ChunkState::Greedy)
         {
-            if self.bg_mesher.mesh(*pos, &self.chunks) {
+            if self.bg_mesher.mesh(*pos, &self.chunks, self.culling) {
                 trace!("Greedy meshing chunk {pos:?}");
             }
         }
@@ -159,9 +407,52 @@ impl ApplicationState {
         self.renderer.render(self.chunk_cache.values()).unwrap();
     }
 
+    /// Get the world's clock, to set or freeze the time of day.
+    pub fn clock(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
     pub fn update(&mut self, dt: Duration) {
+        self.clock.advance(dt);
+        let changes = self.pack.update(dt);
+        if changes.any() {
+            debug!("Resource pack changed: {changes:?}");
+            self.reload(changes);
+        }
+        self.renderer.light(self.clock.light());
+        self.renderer.set_sky(self.clock.sky());
+
         self.player.update(self.renderer.size, dt);
         self.renderer.update_camera(&self.player.camera);
+        self.check_distances();
+    }
+
+    /// Free the meshes of chunks that are too far away to be seen, and queue
+    /// for meshing evicted chunks that are back in view, and chunks whose
+    /// stale lower levels of detail are about to be seen.
+    fn check_distances(&mut self) {
+        let eye = self.player.camera.eye.into_array();
+        let lod_distance = self.renderer.lod_distance();
+        for (&pos, chunk) in &mut self.chunks {
+            let distance = chunk::distance(pos, eye);
+            if distance > VIEW_DISTANCE + EVICT_MARGIN {
+                if let Some(cached) = self.chunk_cache.remove(&pos) {
+                    trace!("Evicting chunk {pos:?}");
+                    self.renderer.uncache(cached);
+                }
+                chunk.state = ChunkState::Evicted;
+                continue;
+            }
+
+            let back_in_view = chunk.state == ChunkState::Evicted && distance <= VIEW_DISTANCE;
+            let lods_seen = chunk.state == ChunkState::Cached
+                && chunk.stale_lods
+                && chunk::center_distance(pos, eye) >= lod_distance;
+            if back_in_view || lods_seen {
+                chunk.state = ChunkState::Remesh;
+                self.changed = true;
+            }
+        }
     }
 
     #[allow(unused)]
@@ -173,8 +464,62 @@ impl ApplicationState {
         if !self.player.process_events(event) && event.state == ElementState::Pressed {
             match &event.logical_key {
                 Key::Named(NamedKey::Escape) => self.exit = true,
+                Key::Named(NamedKey::F2) => self.save_screenshot(1),
+                Key::Named(NamedKey::F3) => self.save_screenshot(POSTER_SCALE),
                 Key::Character(ch) => match ch.as_str() {
-                    "t" | "T" => self.renderer.next_pipeline(),
+                    "t" | "T" => {
+                        let mut mode = self.renderer.render_mode().next();
+                        while !self.renderer.supports(mode) {
+                            mode = mode.next();
+                        }
+                        debug!("Render mode: {mode:?}");
+                        self.renderer.set_render_mode(mode);
+                    }
+                    // toggle each post effect, in the order they're run
+                    "1" | "2" | "3" | "4" | "5" => {
+                        let effect = PostEffect::ALL[ch.parse::<usize>().unwrap() - 1];
+                        let enabled = self.renderer.toggle_post_effect(effect);
+                        debug!("{effect:?}: {enabled}");
+                    }
+                    "o" | "O" => {
+                        let enabled = self.renderer.toggle_occlusion_culling();
+                        debug!("Occlusion culling: {enabled}");
+                    }
+                    "m" | "M" => {
+                        let missing = match self.culling.missing {
+                            MissingNeighbors::Cull => MissingNeighbors::Emit,
+                            MissingNeighbors::Emit => MissingNeighbors::Cull,
+                        };
+                        debug!("Meshing faces bordering missing chunks: {missing:?}");
+                        self.set_culling(Culling { missing });
+                    }
+                    "l" | "L" => {
+                        // place or pick up a lamp where the camera is
+                        let pos = self.player.camera.eye.map(|c| c.floor() as i32);
+                        let block = match self.block(pos.into_array()) {
+                            Some(Block::Lamp) => Block::Air,
+                            _ => Block::Lamp,
+                        };
+                        self.set_block(pos.into_array(), block);
+                    }
+                    "p" | "P" => {
+                        self.clock.frozen = !self.clock.frozen;
+                        debug!("Time frozen: {}", self.clock.frozen);
+                    }
+                    // skip an hour forward or back
+                    "]" | "[" => {
+                        let hour = if ch == "]" { 1.0 / 24.0 } else { -1.0 / 24.0 };
+                        self.clock.set_time(self.clock.time() + hour);
+                        debug!("Time of day: {:.2}", self.clock.time());
+                    }
+                    "b" | "B" => {
+                        let terrain = match self.terrain {
+                            Terrain::Blocky => Terrain::Smooth,
+                            Terrain::Smooth => Terrain::Blocky,
+                        };
+                        debug!("Terrain: {terrain:?}");
+                        self.set_terrain(terrain);
+                    }
                     _ => {}
                 },
                 _ => {}
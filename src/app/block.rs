@@ -1,4 +1,6 @@
-use super::voxel::{Face, Voxel};
+use super::light::MAX_LIGHT;
+use super::voxel::{Face, Voxel, VoxelSide};
+use crate::renderer::{mesh::Layer, Material};
 
 #[repr(u8)]
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
@@ -9,9 +11,31 @@ pub enum Block {
     Grass,
     Dirt,
     Stone,
+    Lamp,
 }
 
 impl Block {
+    pub const ALL: [Block; 6] = [
+        Block::Air,
+        Block::Water,
+        Block::Grass,
+        Block::Dirt,
+        Block::Stone,
+        Block::Lamp,
+    ];
+
+    /// The name the block goes by in resource packs.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Block::Air => "air",
+            Block::Water => "water",
+            Block::Grass => "grass",
+            Block::Dirt => "dirt",
+            Block::Stone => "stone",
+            Block::Lamp => "lamp",
+        }
+    }
+
     pub fn voxel(self) -> Voxel {
         VOXELS[self as usize]
     }
@@ -23,17 +47,60 @@ impl Block {
             _ => false,
         }
     }
+
+    /// The level of block light this block gives off.
+    pub const fn emission(self) -> u8 {
+        match self {
+            Block::Lamp => MAX_LIGHT,
+            _ => 0,
+        }
+    }
+
+    /// The index of the color of one of this block's sides in the
+    /// [`palette`].
+    pub const fn color_id(self, side: VoxelSide) -> u32 {
+        self as u32 * 3 + side as u32
+    }
+
+    /// The layer this block's faces are drawn in.
+    pub const fn layer(self) -> Layer {
+        match self {
+            Block::Water => Layer::Translucent,
+            _ => Layer::Opaque,
+        }
+    }
+}
+
+/// Collect the look of every side of every block, indexed by
+/// [`Block::color_id`].
+pub fn palette() -> Vec<Material> {
+    Block::ALL
+        .into_iter()
+        .flat_map(|block| {
+            // blocks glow as brightly as the light they give off
+            let emission = f32::from(block.emission()) / f32::from(MAX_LIGHT);
+            block.voxel().faces.map(|face| Material {
+                color: face.color,
+                texture: face.texture.map(str::to_owned),
+                emission,
+            })
+        })
+        .collect()
 }
 
 pub const VOXELS: &[Voxel] = &[
     Voxel {
         // Air
-        faces: [Face { color: [0.0; 4] }; 3],
+        faces: [Face {
+            color: [0.0; 4],
+            texture: None,
+        }; 3],
     },
     Voxel {
         // Water
         faces: [Face {
             color: [0.0, 0.3, 0.7, 0.2],
+            texture: Some("water"),
         }; 3],
     },
     Voxel {
@@ -41,12 +108,15 @@ pub const VOXELS: &[Voxel] = &[
         faces: [
             Face {
                 color: [0.22, 0.56, 0.24, 1.0],
+                texture: Some("grass"),
             },
             Face {
                 color: [0.529, 0.243, 0.137, 1.0],
+                texture: Some("dirt"),
             },
             Face {
                 color: [0.529, 0.243, 0.137, 1.0],
+                texture: Some("dirt"),
             },
         ],
     },
@@ -54,12 +124,21 @@ pub const VOXELS: &[Voxel] = &[
         // Dirt
         faces: [Face {
             color: [0.529, 0.243, 0.137, 1.0],
+            texture: Some("dirt"),
         }; 3],
     },
     Voxel {
         // Stone
         faces: [Face {
             color: [0.62, 0.62, 0.62, 1.0],
+            texture: Some("stone"),
+        }; 3],
+    },
+    Voxel {
+        // Lamp
+        faces: [Face {
+            color: [1.0, 0.85, 0.55, 1.0],
+            texture: Some("lamp"),
         }; 3],
     },
 ];
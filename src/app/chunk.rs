@@ -1,19 +1,60 @@
 use super::block::Block;
+use super::light::Light;
 
 pub const CHUNK_SIZE: usize = 32;
 
+/// Split a world-space block position into the position of its chunk and its
+/// position within the chunk.
+pub fn locate(pos: [i32; 3]) -> ([i32; 3], [usize; 3]) {
+    let size = CHUNK_SIZE as i32;
+    (
+        pos.map(|c| c.div_euclid(size)),
+        pos.map(|c| c.rem_euclid(size) as usize),
+    )
+}
+
+/// How far a point is from the nearest part of a chunk, in blocks.
+pub fn distance(chunk: [i32; 3], point: [f32; 3]) -> f32 {
+    let size = CHUNK_SIZE as f32;
+    let mut squared = 0.0;
+    for (c, p) in chunk.into_iter().zip(point) {
+        let min = c as f32 * size;
+        let d = (min - p).max(p - (min + size)).max(0.0);
+        squared += d * d;
+    }
+    squared.sqrt()
+}
+
+/// How far a point is from the center of a chunk, in blocks.
+pub fn center_distance(chunk: [i32; 3], point: [f32; 3]) -> f32 {
+    let size = CHUNK_SIZE as f32;
+    chunk
+        .into_iter()
+        .zip(point)
+        .map(|(c, p)| ((c as f32 + 0.5) * size - p).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
+
 #[derive(Clone, Default)]
 pub struct Chunk {
     /// [[[x] z] y]
     pub blocks: [[[Block; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    /// The light level of each block, laid out like `blocks`.
+    pub light: [[[Light; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
     pub state: ChunkState,
+    /// Whether the chunk has changed since its lower levels of detail were
+    /// last meshed.
+    pub stale_lods: bool,
 }
 
 impl Chunk {
     pub const fn new(blocks: [[[Block; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]) -> Self {
         Chunk {
             blocks,
+            light: [[[Light::DARK; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
             state: ChunkState::Remesh,
+            stale_lods: false,
         }
     }
 
@@ -31,6 +72,19 @@ impl Chunk {
             .and_then(|col| col.get_mut(z))
             .and_then(|row| row.get_mut(x))
     }
+
+    /// How many kinds of block other than air the chunk holds. The fewer
+    /// there are, the more faces greedy meshing can merge.
+    pub fn kinds(&self) -> usize {
+        let mut seen = [false; Block::ALL.len()];
+        for &block in self.blocks.iter().flatten().flatten() {
+            seen[block as usize] = true;
+        }
+        Block::ALL
+            .into_iter()
+            .filter(|&block| block != Block::Air && seen[block as usize])
+            .count()
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
@@ -39,4 +93,7 @@ pub enum ChunkState {
     Greedy,
     #[default]
     Remesh,
+    /// Too far away to be seen, so its mesh has been freed. It's remeshed
+    /// once it's back in view.
+    Evicted,
 }
@@ -0,0 +1,171 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use vek::Vec3;
+
+use crate::renderer::{light::LightUniform, sky::Sky};
+
+/// The sun's light at noon.
+const NOON: Vec3<f32> = Vec3::new(1.0, 0.97, 0.9);
+/// The light of either the sun or the moon as it meets the horizon.
+const TWILIGHT: Vec3<f32> = Vec3::new(0.35, 0.2, 0.2);
+/// The moon's light at its highest.
+const MOONLIGHT: Vec3<f32> = Vec3::new(0.12, 0.14, 0.22);
+
+const DAY_AMBIENT: f32 = 0.2;
+/// Nights are lit more evenly than days, with little to cast a shadow.
+const NIGHT_AMBIENT: f32 = 0.5;
+
+const DAY_ZENITH: Vec3<f32> = Vec3::new(0.15, 0.35, 0.9);
+const DAY_HORIZON: Vec3<f32> = Vec3::new(0.55, 0.7, 1.0);
+const NIGHT_ZENITH: Vec3<f32> = Vec3::new(0.002, 0.003, 0.01);
+const NIGHT_HORIZON: Vec3<f32> = Vec3::new(0.01, 0.012, 0.03);
+/// The glow of the sky around sunrise and sunset, strongest at the horizon.
+const TWILIGHT_SKY: Vec3<f32> = Vec3::new(0.8, 0.4, 0.25);
+
+/// How far the sun's path is tilted away from passing straight overhead.
+const TILT: f32 = 0.3;
+
+/// The time of day in a world, and how the sun, moon and sky look at it.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// From 0 to 1, where 0 is midnight, 0.25 sunrise, 0.5 noon and 0.75
+    /// sunset.
+    time: f32,
+    /// How long a whole day lasts.
+    pub day_length: Duration,
+    /// Whether time has stopped.
+    pub frozen: bool,
+}
+
+impl Clock {
+    pub fn new(time: f32) -> Self {
+        let mut clock = Self {
+            time: 0.0,
+            day_length: Duration::from_secs(10 * 60),
+            frozen: false,
+        };
+        clock.set_time(time);
+        clock
+    }
+
+    /// Move time forward by `dt`, unless it's frozen.
+    pub fn advance(&mut self, dt: Duration) {
+        if !self.frozen {
+            self.set_time(self.time + dt.as_secs_f32() / self.day_length.as_secs_f32());
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Set the time of day, wrapping around into the next or previous day.
+    pub fn set_time(&mut self, time: f32) {
+        // just before midnight of the previous day rounds up to 1
+        let time = time.rem_euclid(1.0);
+        self.time = if time < 1.0 { time } else { 0.0 };
+    }
+
+    /// The direction towards the sun, which rises along +x. The moon is
+    /// always opposite.
+    pub fn sun_direction(&self) -> Vec3<f32> {
+        let angle = (self.time - 0.25) * TAU;
+        Vec3::new(angle.cos(), angle.sin(), TILT).normalized()
+    }
+
+    /// The light of whichever of the sun and moon is above the horizon.
+    pub fn light(&self) -> LightUniform {
+        let sun = self.sun_direction();
+        let elevation = sun.y;
+
+        let (direction, color) = if elevation >= 0.0 {
+            (sun, mix(TWILIGHT, NOON, smoothstep(0.0, 0.3, elevation)))
+        } else {
+            (
+                -sun,
+                mix(TWILIGHT, MOONLIGHT, smoothstep(0.0, 0.3, -elevation)),
+            )
+        };
+
+        let mut light = LightUniform::directional(direction.into_array(), color.into_array());
+        light.ambient = NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * self.daylight();
+        light
+    }
+
+    /// How the sky looks, with stars coming out at night.
+    pub fn sky(&self) -> Sky {
+        let sun = self.sun_direction();
+        let daylight = self.daylight();
+
+        // the sky glows as the sun passes the horizon
+        let glow = 1.0 - smoothstep(0.0, 0.25, sun.y.abs());
+        let zenith = mix(NIGHT_ZENITH, DAY_ZENITH, daylight);
+        let horizon = mix(NIGHT_HORIZON, DAY_HORIZON, daylight);
+
+        Sky {
+            zenith: mix(zenith, TWILIGHT_SKY, glow * 0.3).into_array(),
+            horizon: mix(horizon, TWILIGHT_SKY, glow * 0.7).into_array(),
+            sun: sun.into_array(),
+            stars: 1.0 - smoothstep(0.0, 0.6, daylight),
+        }
+    }
+
+    /// How much it's day rather than night, from 0 to 1.
+    fn daylight(&self) -> f32 {
+        smoothstep(-0.15, 0.25, self.sun_direction().y)
+    }
+}
+
+impl Default for Clock {
+    /// Mid-morning.
+    fn default() -> Self {
+        Self::new(0.35)
+    }
+}
+
+fn mix(a: Vec3<f32>, b: Vec3<f32>, t: f32) -> Vec3<f32> {
+    a + (b - a) * t
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around_midnight() {
+        let mut clock = Clock::new(0.9);
+        clock.advance(clock.day_length / 5);
+        assert!((clock.time() - 0.1).abs() < 1e-5, "{}", clock.time());
+
+        // a whole day and a half later
+        clock.advance(clock.day_length * 3 / 2);
+        assert!((clock.time() - 0.6).abs() < 1e-5, "{}", clock.time());
+    }
+
+    #[test]
+    fn stays_put_while_frozen() {
+        let mut clock = Clock::new(0.9);
+        clock.frozen = true;
+        clock.advance(clock.day_length / 5);
+        assert_eq!(clock.time(), 0.9);
+    }
+
+    #[test]
+    fn sets_times_outside_the_day() {
+        let mut clock = Clock::default();
+        for (time, expected) in [(1.25, 0.25), (-0.25, 0.75), (3.0, 0.0), (-1.0, 0.0)] {
+            clock.set_time(time);
+            assert_eq!(clock.time(), expected, "setting {time}");
+        }
+
+        clock.set_time(-1e-9);
+        assert!((0.0..1.0).contains(&clock.time()), "{}", clock.time());
+        assert_eq!(Clock::new(-1e-9).time(), 0.0);
+    }
+}
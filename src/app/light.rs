@@ -0,0 +1,571 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::block::Block;
+use super::chunk::{locate, Chunk, CHUNK_SIZE};
+use super::voxel::Direction;
+
+/// The brightest a block can be lit.
+pub const MAX_LIGHT: u8 = 15;
+
+/// The light levels of a block, from 0 to [`MAX_LIGHT`]: skylight in the
+/// high four bits, and light from emissive blocks in the low four.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Light(pub u8);
+
+impl Light {
+    pub const DARK: Light = Light(0);
+    /// Open to the sky, with no block light.
+    pub const SKY: Light = Light(MAX_LIGHT << 4);
+
+    pub const fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub const fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    /// The brighter of each level of two lights.
+    pub fn max(self, other: Light) -> Light {
+        Light(self.0.max(other.0) & 0xf0 | self.block().max(other.block()))
+    }
+
+    fn get(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => self.sky(),
+            Channel::Block => self.block(),
+        }
+    }
+
+    fn set(&mut self, channel: Channel, level: u8) {
+        self.0 = match channel {
+            Channel::Sky => self.0 & 0xf | level << 4,
+            Channel::Block => self.0 & 0xf0 | level,
+        };
+    }
+}
+
+/// The two kinds of light, which spread independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+/// Light every loaded chunk from scratch.
+///
+/// Skylight shines straight down from the top of the world until it hits
+/// something that isn't air, and both kinds of light spread out from there
+/// through transparent blocks, losing a level for every block. Returns the
+/// chunks whose light changed.
+pub fn light_all(chunks: &mut HashMap<[i32; 3], Chunk>) -> HashSet<[i32; 3]> {
+    let positions: Vec<_> = chunks.keys().copied().collect();
+    let mut old = HashMap::new();
+    let mut world = World::new(chunks);
+    let mut sky = VecDeque::new();
+    let mut emitters = VecDeque::new();
+
+    for &[cx, cy, cz] in &positions {
+        let chunk = world.chunks.get_mut(&[cx, cy, cz]).unwrap();
+        old.insert([cx, cy, cz], std::mem::take(&mut chunk.light));
+
+        let origin = [cx, cy, cz].map(|c| c * CHUNK_SIZE as i32);
+        for (y, layer) in chunk.blocks.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, block) in row.iter().enumerate() {
+                    if block.emission() > 0 {
+                        let pos = [x, y, z].map(|c| c as i32);
+                        emitters.push_back([0, 1, 2].map(|i| origin[i] + pos[i]));
+                    }
+                }
+            }
+        }
+    }
+
+    // the top of each column of sky, and the lowest block it reaches
+    let mut columns = HashMap::new();
+    for &[cx, cy, cz] in &positions {
+        // skylight enters from the top of each column of chunks
+        if world.chunks.contains_key(&[cx, cy + 1, cz]) {
+            continue;
+        }
+
+        let top = (cy + 1) * CHUNK_SIZE as i32 - 1;
+        for z in 0..CHUNK_SIZE as i32 {
+            for x in 0..CHUNK_SIZE as i32 {
+                let [x, z] = [cx * CHUNK_SIZE as i32 + x, cz * CHUNK_SIZE as i32 + z];
+                let mut y = top;
+                while world.block([x, y, z]) == Some(Block::Air) {
+                    world.set_light([x, y, z], Channel::Sky, MAX_LIGHT);
+                    y -= 1;
+                }
+                columns.insert([x, z], (top, y + 1));
+            }
+        }
+    }
+
+    // light can only spread sideways out of a column where its neighbors
+    // are darker, and down out of its bottom
+    for (&[x, z], &(top, bottom)) in &columns {
+        let darker_below = [[x - 1, z], [x + 1, z], [x, z - 1], [x, z + 1]]
+            .iter()
+            .filter_map(|neighbor| columns.get(neighbor))
+            .map(|&(_, bottom)| bottom)
+            .max()
+            .unwrap_or(bottom);
+
+        for y in bottom..=top.min(darker_below.max(bottom)) {
+            sky.push_back([x, y, z]);
+        }
+    }
+
+    for &pos in &emitters {
+        let emission = world.block(pos).unwrap().emission();
+        world.set_light(pos, Channel::Block, emission);
+    }
+
+    world.spread(Channel::Sky, sky);
+    world.spread(Channel::Block, emitters);
+
+    positions
+        .into_iter()
+        .filter(|pos| chunks[pos].light != old[pos])
+        .collect()
+}
+
+/// Add a chunk to the world, lighting it and updating the light around it.
+///
+/// Light spreads in from the sky above the chunk, if there's no chunk there,
+/// from its own emissive blocks, and from its loaded neighbors. A chunk
+/// loaded below it no longer sees the sky through it, unless the sky still
+/// shines straight down through the new chunk; this includes filling a gap
+/// in a column, below which the sky used to enter. Returns the chunks whose
+/// light changed, including the new one.
+pub fn insert_chunk(
+    chunks: &mut HashMap<[i32; 3], Chunk>,
+    at: [i32; 3],
+    mut chunk: Chunk,
+) -> HashSet<[i32; 3]> {
+    let size = CHUNK_SIZE as i32;
+    let origin = at.map(|c| c * size);
+    let mut sky = VecDeque::new();
+    let mut emitters = VecDeque::new();
+    let mut shaded = VecDeque::new();
+
+    for (y, layer) in chunk.blocks.iter().enumerate() {
+        for (z, row) in layer.iter().enumerate() {
+            for (x, block) in row.iter().enumerate() {
+                if block.emission() > 0 {
+                    let pos = [x, y, z].map(|c| c as i32);
+                    emitters.push_back([0, 1, 2].map(|i| origin[i] + pos[i]));
+                }
+            }
+        }
+    }
+
+    chunk.light = [[[Light::DARK; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+    chunks.insert(at, chunk);
+    let mut world = World::new(chunks);
+    world.changed.insert(at);
+
+    // skylight enters from the top of each column of chunks, which this now
+    // is if there's nothing above it, and shines straight down through the
+    // chunk wherever it reaches the block above at full strength
+    let above = world.chunks.contains_key(&[at[0], at[1] + 1, at[2]]);
+    for z in origin[2]..origin[2] + size {
+        for x in origin[0]..origin[0] + size {
+            let top = origin[1] + size - 1;
+            let sunlit = !above
+                || world
+                    .light([x, top + 1, z])
+                    .is_some_and(|light| light.sky() == MAX_LIGHT);
+
+            let mut y = top;
+            while sunlit && y >= origin[1] && world.block([x, y, z]) == Some(Block::Air) {
+                world.set_light([x, y, z], Channel::Sky, MAX_LIGHT);
+                sky.push_back([x, y, z]);
+                y -= 1;
+            }
+
+            // the sky no longer shines straight down into the chunk below,
+            // which used to be the top of the column if there's a gap here
+            let below = [x, origin[1] - 1, z];
+            let blocked = y >= origin[1];
+            if blocked && world.light(below).is_some_and(|l| l.sky() == MAX_LIGHT) {
+                world.set_light(below, Channel::Sky, 0);
+                shaded.push_back((below, MAX_LIGHT));
+            }
+        }
+    }
+
+    for &pos in &emitters {
+        let emission = world.block(pos).unwrap().emission();
+        world.set_light(pos, Channel::Block, emission);
+    }
+
+    sky.extend(world.unspread(Channel::Sky, shaded));
+
+    // whatever is lit along the neighbors' borders spreads in
+    for direction in Direction::ALL {
+        let normal = direction.normal();
+        let axis = normal.iter().position(|&n| n != 0).unwrap();
+        let [u, v] = [(axis + 1) % 3, (axis + 2) % 3];
+        for a in 0..size {
+            for b in 0..size {
+                let mut border = origin;
+                border[axis] += if normal[axis] > 0 { size } else { -1 };
+                border[u] += a;
+                border[v] += b;
+
+                let Some(light) = world.light(border) else {
+                    continue;
+                };
+                if light.sky() > 0 {
+                    sky.push_back(border);
+                }
+                if light.block() > 0 {
+                    emitters.push_back(border);
+                }
+            }
+        }
+    }
+
+    world.spread(Channel::Sky, sky);
+    world.spread(Channel::Block, emitters);
+
+    world.changed
+}
+
+/// Place a block, updating the light around it.
+///
+/// `pos` is in world space. Returns the chunks whose blocks or light
+/// changed, which is nothing if `pos` isn't loaded.
+pub fn set_block(
+    chunks: &mut HashMap<[i32; 3], Chunk>,
+    pos: [i32; 3],
+    block: Block,
+) -> HashSet<[i32; 3]> {
+    let mut world = World::new(chunks);
+    if world.block(pos).is_none() {
+        return HashSet::new();
+    }
+
+    let (chunk, [x, y, z]) = locate(pos);
+    world.chunks.get_mut(&chunk).unwrap().blocks[y][z][x] = block;
+    world.changed.insert(chunk);
+
+    for channel in [Channel::Sky, Channel::Block] {
+        // take away whatever light reached through the block, then fill it
+        // back in from whatever is still lit
+        let level = world.light(pos).unwrap().get(channel);
+        world.set_light(pos, channel, 0);
+        let mut relight = world.unspread(channel, VecDeque::from([(pos, level)]));
+
+        if channel == Channel::Block && block.emission() > 0 {
+            world.set_light(pos, channel, block.emission());
+            relight.push_back(pos);
+        }
+
+        if block.is_transparent() {
+            let above = [pos[0], pos[1] + 1, pos[2]];
+            if channel == Channel::Sky && block == Block::Air && world.block(above).is_none() {
+                world.set_light(pos, channel, MAX_LIGHT);
+                relight.push_back(pos);
+            }
+
+            for direction in Direction::ALL {
+                let next = offset(pos, direction);
+                if world
+                    .light(next)
+                    .is_some_and(|light| light.get(channel) > 0)
+                {
+                    relight.push_back(next);
+                }
+            }
+        }
+
+        world.spread(channel, relight);
+    }
+
+    world.changed
+}
+
+fn offset(pos: [i32; 3], direction: Direction) -> [i32; 3] {
+    let normal = direction.normal();
+    [0, 1, 2].map(|i| pos[i] + normal[i])
+}
+
+/// Loaded chunks, addressed by world-space block positions.
+struct World<'a> {
+    chunks: &'a mut HashMap<[i32; 3], Chunk>,
+    /// The chunks whose light has changed.
+    changed: HashSet<[i32; 3]>,
+}
+
+impl<'a> World<'a> {
+    fn new(chunks: &'a mut HashMap<[i32; 3], Chunk>) -> Self {
+        Self {
+            chunks,
+            changed: HashSet::new(),
+        }
+    }
+
+    fn block(&self, pos: [i32; 3]) -> Option<Block> {
+        let (chunk, [x, y, z]) = locate(pos);
+        self.chunks.get(&chunk).map(|chunk| chunk.blocks[y][z][x])
+    }
+
+    fn light(&self, pos: [i32; 3]) -> Option<Light> {
+        let (chunk, [x, y, z]) = locate(pos);
+        self.chunks.get(&chunk).map(|chunk| chunk.light[y][z][x])
+    }
+
+    fn set_light(&mut self, pos: [i32; 3], channel: Channel, level: u8) {
+        let (chunk_pos, [x, y, z]) = locate(pos);
+        let chunk = self.chunks.get_mut(&chunk_pos).unwrap();
+        chunk.light[y][z][x].set(channel, level);
+        self.changed.insert(chunk_pos);
+    }
+
+    /// The level of `channel` that reaches `block` from a neighbor at
+    /// `level`, travelling in `direction`.
+    fn falloff(channel: Channel, level: u8, direction: Direction, block: Block) -> u8 {
+        // sunlight shines straight down through air without fading
+        if channel == Channel::Sky
+            && level == MAX_LIGHT
+            && direction == Direction::Bottom
+            && block == Block::Air
+        {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+
+    /// Flood fill light outwards from each of `queue`'s blocks.
+    fn spread(&mut self, channel: Channel, mut queue: VecDeque<[i32; 3]>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = self.light(pos).unwrap().get(channel);
+            if level <= 1 {
+                continue;
+            }
+
+            for direction in Direction::ALL {
+                let next = offset(pos, direction);
+                let Some(block) = self.block(next).filter(|block| block.is_transparent()) else {
+                    continue;
+                };
+
+                let reached = Self::falloff(channel, level, direction, block);
+                if reached > self.light(next).unwrap().get(channel) {
+                    self.set_light(next, channel, reached);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    /// Darken every block lit by the already darkened blocks in `queue`,
+    /// given with the levels they had.
+    ///
+    /// Returns the blocks lit from elsewhere that border the darkened area,
+    /// which need to [`spread`](Self::spread) back into it.
+    fn unspread(
+        &mut self,
+        channel: Channel,
+        mut queue: VecDeque<([i32; 3], u8)>,
+    ) -> VecDeque<[i32; 3]> {
+        let mut relight = VecDeque::new();
+
+        while let Some((pos, level)) = queue.pop_front() {
+            for direction in Direction::ALL {
+                let next = offset(pos, direction);
+                let (Some(block), Some(light)) = (self.block(next), self.light(next)) else {
+                    continue;
+                };
+
+                let current = light.get(channel);
+                if current == 0 {
+                    continue;
+                }
+
+                // anything dimmer than `pos` may have been lit by it, as may
+                // sunlight shining straight down from it
+                let lit_by = current < level
+                    || current == MAX_LIGHT
+                        && Self::falloff(channel, level, direction, block) == MAX_LIGHT;
+                if lit_by {
+                    self.set_light(next, channel, 0);
+                    queue.push_back((next, current));
+
+                    if channel == Channel::Block && block.emission() > 0 {
+                        self.set_light(next, channel, block.emission());
+                        relight.push_back(next);
+                    }
+                } else {
+                    relight.push_back(next);
+                }
+            }
+        }
+
+        relight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::worldgen;
+
+    /// Flat ground with a lamp in it, and a partly roofed chunk of air on
+    /// top of one corner, holding another lamp.
+    fn world() -> Vec<([i32; 3], Chunk)> {
+        let mut chunks: Vec<_> = worldgen::gen(0, [0, 0, 0], [2, 1, 2]).into_iter().collect();
+        chunks.sort_by_key(|&(pos, _)| pos);
+        chunks[0].1.blocks[31][30][30] = Block::Lamp;
+
+        let mut above = Chunk::new([[[Block::Air; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE / 2 {
+                above.blocks[10][z][x] = Block::Stone;
+            }
+        }
+        above.blocks[5][4][4] = Block::Lamp;
+        chunks.push(([0, 1, 0], above));
+        chunks
+    }
+
+    /// Insert `order`'s chunks one by one, checking that they end up lit the
+    /// same as lighting them all at once.
+    fn assert_lit_like_light_all(order: Vec<([i32; 3], Chunk)>) {
+        let mut expected: HashMap<_, _> = order.iter().cloned().collect();
+        light_all(&mut expected);
+
+        let mut chunks = HashMap::new();
+        for (pos, chunk) in order {
+            let changed = insert_chunk(&mut chunks, pos, chunk);
+            assert!(changed.contains(&pos));
+        }
+
+        for (pos, chunk) in &expected {
+            assert!(
+                chunks[pos].light == chunk.light,
+                "chunk {pos:?} is lit differently"
+            );
+        }
+    }
+
+    #[test]
+    fn inserts_chunks_below() {
+        assert_lit_like_light_all(world().into_iter().rev().collect());
+    }
+
+    #[test]
+    fn inserts_chunks_above() {
+        assert_lit_like_light_all(world());
+    }
+
+    #[test]
+    fn fills_gaps_in_columns() {
+        // the roofed chunk goes in last, between the ground and a stone slab
+        // with a hole in it
+        let mut chunks = world();
+        let middle = chunks.pop().unwrap();
+        let mut slab = Chunk::new([[[Block::Air; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        slab.blocks[0] = [[Block::Stone; CHUNK_SIZE]; CHUNK_SIZE];
+        slab.blocks[0][20][20] = Block::Air;
+        chunks.push(([0, 2, 0], slab));
+        chunks.push(middle);
+
+        assert_lit_like_light_all(chunks.clone());
+        chunks.reverse();
+        assert_lit_like_light_all(chunks);
+    }
+
+    #[test]
+    fn inserting_returns_changed_chunks() {
+        let mut chunks = HashMap::new();
+        let mut world = world().into_iter();
+        let (ground, chunk) = world.next().unwrap();
+        insert_chunk(&mut chunks, ground, chunk);
+
+        // roofing the ground over darkens it, but a chunk off to the side
+        // with nothing lit along its border is left alone
+        let (above, chunk) = world.last().unwrap();
+        let changed = insert_chunk(&mut chunks, above, chunk);
+        assert_eq!(changed, HashSet::from([ground, above]));
+
+        let empty = Chunk::new([[[Block::Stone; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        let changed = insert_chunk(&mut chunks, [5, 0, 5], empty);
+        assert_eq!(changed, HashSet::from([[5, 0, 5]]));
+    }
+
+    /// Place a block in the lit world, checking that it ends up lit the same
+    /// as lighting it from scratch, and that exactly the chunks whose blocks
+    /// or light changed are returned.
+    fn assert_sets_like_light_all(
+        chunks: &mut HashMap<[i32; 3], Chunk>,
+        pos: [i32; 3],
+        block: Block,
+    ) {
+        let before = chunks.clone();
+        let changed = set_block(chunks, pos, block);
+
+        let mut expected = chunks.clone();
+        light_all(&mut expected);
+        for (chunk_pos, chunk) in &expected {
+            assert!(
+                chunks[chunk_pos].light == chunk.light,
+                "chunk {chunk_pos:?} is lit differently after placing {block:?} at {pos:?}"
+            );
+        }
+
+        let differ: HashSet<_> = chunks
+            .iter()
+            .filter(|(chunk_pos, chunk)| {
+                let before = &before[*chunk_pos];
+                chunk.blocks != before.blocks || chunk.light != before.light
+            })
+            .map(|(&chunk_pos, _)| chunk_pos)
+            .collect();
+        assert_eq!(changed, differ, "after placing {block:?} at {pos:?}");
+    }
+
+    fn lit_world() -> HashMap<[i32; 3], Chunk> {
+        let mut chunks = world().into_iter().collect();
+        light_all(&mut chunks);
+        chunks
+    }
+
+    #[test]
+    fn shades_and_reopens_sunlit_columns() {
+        let mut chunks = lit_world();
+        // in the open half of the chunk above the ground, which the sky
+        // reaches all the way down into the ground
+        let pos = [20, 60, 4];
+        assert_eq!(chunks[&[0, 0, 0]].light[31][4][20], Light::SKY);
+
+        assert_sets_like_light_all(&mut chunks, pos, Block::Stone);
+        assert!(chunks[&[0, 0, 0]].light[31][4][20].sky() < MAX_LIGHT);
+        assert_sets_like_light_all(&mut chunks, pos, Block::Air);
+        assert_eq!(chunks[&[0, 0, 0]].light[31][4][20], Light::SKY);
+    }
+
+    #[test]
+    fn places_and_removes_lamps() {
+        let mut chunks = lit_world();
+        // under the roof, by the corner of the ground chunks
+        let pos = [15, 33, 31];
+
+        assert_sets_like_light_all(&mut chunks, pos, Block::Lamp);
+        assert!(chunks[&[0, 0, 1]].light[31][0][15].block() > 0);
+        assert_sets_like_light_all(&mut chunks, pos, Block::Air);
+        assert_eq!(chunks[&[0, 1, 0]].light[1][31][15].block(), 0);
+    }
+
+    #[test]
+    fn ignores_unloaded_blocks() {
+        let mut chunks = lit_world();
+        assert!(set_block(&mut chunks, [0, 200, 0], Block::Stone).is_empty());
+    }
+}
@@ -1,35 +1,85 @@
 use std::collections::HashMap;
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
 use std::thread;
+use std::time::Instant;
 
-use super::chunk::Chunk;
-use crate::renderer::mesh::Mesh;
+use super::block::Block;
+use super::chunk::{Chunk, CHUNK_SIZE};
+use super::light::Light;
+use crate::renderer::mesh::{ChunkMesh, Mesher};
 
+mod ao;
+mod cull;
 #[allow(clippy::too_many_arguments)]
 mod fast;
 #[allow(clippy::too_many_arguments)]
 mod greedy;
+mod lod;
+mod policy;
+mod smooth;
+mod visibility;
 
+pub use cull::{Culling, MissingNeighbors};
 pub use fast::fast;
 pub use greedy::greedy;
+pub use policy::MeshPolicy;
+pub use smooth::{smooth, smooth_scaled};
+use visibility::visibility;
+
+/// Mesh a chunk with the given mesher, recording statistics about the
+/// result and which of its faces can see each other.
+///
+/// If `lods` is set, the chunk's lower levels of detail are meshed too;
+/// otherwise whichever levels are already cached are kept. If `optimize` is
+/// set, the meshes are also reordered for the vertex cache.
+pub fn mesh(
+    mesher: Mesher,
+    pos: [i32; 3],
+    region: &ChunkRegion,
+    lods: bool,
+    optimize: bool,
+) -> ChunkMesh {
+    let start = Instant::now();
+    let mut mesh = match mesher {
+        Mesher::Fast => fast(pos, region),
+        Mesher::Greedy => greedy(pos, region),
+        Mesher::Smooth => smooth(pos, region),
+    };
+    let time = start.elapsed();
+
+    if lods {
+        mesh.lods = lod::lods(mesher, pos, region);
+    }
+    if optimize {
+        mesh.optimize();
+    }
+    mesh.finish(mesher, time);
+    mesh.visibility = visibility(region);
+    mesh
+}
 
 pub struct BgMesher {
     pub send: SyncSender<([i32; 3], ChunkRegion)>,
-    pub recv: Receiver<([i32; 3], Mesh)>,
+    pub recv: Receiver<([i32; 3], ChunkMesh)>,
     pub full: bool,
     closed: bool,
 }
 
 impl BgMesher {
-    pub const MESHER: fn([i32; 3], ChunkRegion) -> Mesh = greedy;
+    pub const MESHER: Mesher = Mesher::Greedy;
+    /// Background meshing isn't time-critical, so it may as well produce
+    /// cache-friendly meshes.
+    pub const OPTIMIZE: bool = true;
 
     pub fn new() -> Self {
         let (mesh_tx, recv) = sync_channel(1);
         let (send, mesh_rx) = sync_channel(0);
 
         thread::spawn(move || {
-            while let Ok((pos, chunks)) = mesh_rx.recv() {
-                let Ok(_) = mesh_tx.send((pos, Self::MESHER(pos, chunks))) else {
+            while let Ok((pos, region)) = mesh_rx.recv() {
+                let Ok(_) =
+                    mesh_tx.send((pos, mesh(Self::MESHER, pos, &region, true, Self::OPTIMIZE)))
+                else {
                     break;
                 };
             }
@@ -43,29 +93,24 @@ impl BgMesher {
         }
     }
 
-    pub fn mesh(&mut self, [x, y, z]: [i32; 3], chunks: &HashMap<[i32; 3], Chunk>) -> bool {
+    pub fn mesh(
+        &mut self,
+        pos: [i32; 3],
+        chunks: &HashMap<[i32; 3], Chunk>,
+        culling: Culling,
+    ) -> bool {
         if self.closed || self.full {
             return false;
         }
 
         self.full = true;
 
-        let region = ChunkRegion {
-            center: chunks.get(&[x, y, z]).unwrap().clone(),
-
-            left: chunks.get(&[x - 1, y, z]).cloned(),
-            right: chunks.get(&[x + 1, y, z]).cloned(),
-            top: chunks.get(&[x, y + 1, z]).cloned(),
-            bot: chunks.get(&[x, y - 1, z]).cloned(),
-            front: chunks.get(&[x, y, z - 1]).cloned(),
-            back: chunks.get(&[x, y, z + 1]).cloned(),
-        };
-
-        self.closed = self.send.send(([x, y, z], region)).is_err();
+        let region = ChunkRegion::new(pos, chunks, culling);
+        self.closed = self.send.send((pos, region)).is_err();
         !self.closed
     }
 
-    pub fn query(&mut self) -> Option<([i32; 3], Mesh)> {
+    pub fn query(&mut self) -> Option<([i32; 3], ChunkMesh)> {
         let data = self.recv.try_recv();
         if matches!(data, Err(TryRecvError::Disconnected)) {
             self.closed = true;
@@ -76,13 +121,111 @@ impl BgMesher {
     }
 }
 
+/// Get the world-space position of a chunk's lowest corner.
+fn origin(pos: [i32; 3]) -> [f32; 3] {
+    pos.map(|c| c as f32 * CHUNK_SIZE as f32)
+}
+
+/// The number of blocks along each axis of a [`ChunkRegion`].
+const REGION_SIZE: usize = CHUNK_SIZE + 2;
+
+/// A chunk, along with a one-block border taken from its 26 neighbors.
 pub struct ChunkRegion {
-    center: Chunk,
-
-    left: Option<Chunk>,
-    right: Option<Chunk>,
-    top: Option<Chunk>,
-    bot: Option<Chunk>,
-    front: Option<Chunk>,
-    back: Option<Chunk>,
+    /// [[[x] z] y], offset by one; `None` where the neighbor isn't loaded.
+    blocks: Box<[[[Option<Block>; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]>,
+    /// The light level of each block, laid out like `blocks`; lit by the sky
+    /// where the neighbor isn't loaded.
+    light: Box<[[[Light; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]>,
+    culling: Culling,
+}
+
+impl ChunkRegion {
+    pub fn new([x, y, z]: [i32; 3], chunks: &HashMap<[i32; 3], Chunk>, culling: Culling) -> Self {
+        let mut blocks = Box::new([[[None; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]);
+        let mut light = Box::new([[[Light::SKY; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]);
+
+        // the part of a neighbor that borders the center chunk along one axis
+        let span = |d: i32| match d {
+            -1 => CHUNK_SIZE - 1..CHUNK_SIZE,
+            0 => 0..CHUNK_SIZE,
+            _ => 0..1,
+        };
+        let offset = |d: i32, i: usize| (d * CHUNK_SIZE as i32 + i as i32 + 1) as usize;
+
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                for dx in -1..=1 {
+                    let Some(chunk) = chunks.get(&[x + dx, y + dy, z + dz]) else {
+                        continue;
+                    };
+
+                    for by in span(dy) {
+                        for bz in span(dz) {
+                            for bx in span(dx) {
+                                let [y, z, x] = [offset(dy, by), offset(dz, bz), offset(dx, bx)];
+                                blocks[y][z][x] = Some(chunk.blocks[by][bz][bx]);
+                                light[y][z][x] = chunk.light[by][bz][bx];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Self {
+            blocks,
+            light,
+            culling,
+        }
+    }
+
+    /// Get a block relative to the center chunk, where each coordinate
+    /// ranges from `-1` to `CHUNK_SIZE`.
+    ///
+    /// Returns `None` if the block lies in a neighbor that isn't loaded.
+    pub fn get(&self, [x, y, z]: [i32; 3]) -> Option<Block> {
+        self.blocks[(y + 1) as usize][(z + 1) as usize][(x + 1) as usize]
+    }
+
+    /// Get a block from the center chunk.
+    pub fn block(&self, [x, y, z]: [usize; 3]) -> Block {
+        self.blocks[y + 1][z + 1][x + 1].unwrap_or_default()
+    }
+
+    /// Get the light level of a block, as per [`ChunkRegion::get`].
+    pub fn light(&self, [x, y, z]: [i32; 3]) -> Light {
+        self.light[(y + 1) as usize][(z + 1) as usize][(x + 1) as usize]
+    }
+
+    pub fn culling(&self) -> Culling {
+        self.culling
+    }
+
+    /// Whether the face of `block` touching the block at `neighbor` should be
+    /// drawn, as per [`ChunkRegion::get`].
+    pub fn is_visible(&self, block: Block, neighbor: [i32; 3]) -> bool {
+        self.culling.is_visible(block, self.get(neighbor))
+    }
+}
+
+#[cfg(test)]
+impl ChunkRegion {
+    /// A region lit by the sky, with each block given by `block`, as per
+    /// [`ChunkRegion::get`].
+    pub fn from_fn(culling: Culling, block: impl Fn([i32; 3]) -> Option<Block>) -> Self {
+        let mut blocks = Box::new([[[None; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]);
+        for (y, layer) in blocks.iter_mut().enumerate() {
+            for (z, row) in layer.iter_mut().enumerate() {
+                for (x, cell) in row.iter_mut().enumerate() {
+                    *cell = block([x, y, z].map(|c| c as i32 - 1));
+                }
+            }
+        }
+
+        Self {
+            blocks,
+            light: Box::new([[[Light::SKY; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]),
+            culling,
+        }
+    }
 }
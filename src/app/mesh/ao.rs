@@ -0,0 +1,103 @@
+use super::ChunkRegion;
+
+/// Compute the ambient occlusion level (0-3) of each corner of a face.
+///
+/// `pos` is the voxel the face belongs to, and `normal` is the sign of the
+/// face's normal along `ci`. Corners are ordered `[00, 01, 11, 10]` along the
+/// `ai` and `bi` axes.
+pub fn occlusion(
+    region: &ChunkRegion,
+    mut pos: [i32; 3],
+    [ai, bi, ci]: [usize; 3],
+    normal: i32,
+) -> [u8; 4] {
+    // the corners are determined by the voxels in front of the face
+    pos[ci] += normal;
+
+    let solid = |da: i32, db: i32| {
+        let mut at = pos;
+        at[ai] += da;
+        at[bi] += db;
+        region.get(at).is_some_and(|block| !block.is_transparent()) as u8
+    };
+
+    [(-1, -1), (-1, 1), (1, 1), (1, -1)].map(|(da, db)| {
+        let side1 = solid(da, 0);
+        let side2 = solid(0, db);
+        if side1 == 1 && side2 == 1 {
+            0
+        } else {
+            3 - side1 - side2 - solid(da, db)
+        }
+    })
+}
+
+/// Get the order in which to emit the corners of a quad as two triangles.
+///
+/// Corners are ordered as in [`occlusion`]. The quad is split along the
+/// brighter diagonal, so that occlusion is interpolated consistently
+/// regardless of the quad's orientation.
+pub fn triangulate([c00, c01, c11, c10]: [u8; 4], clockwise: bool) -> [usize; 6] {
+    let flip = c00 + c11 < c01 + c10;
+    match (flip, clockwise) {
+        (false, false) => [0, 1, 2, 2, 3, 0],
+        (false, true) => [2, 1, 0, 0, 3, 2],
+        (true, false) => [1, 2, 3, 3, 0, 1],
+        (true, true) => [3, 2, 1, 1, 0, 3],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::block::Block;
+    use crate::app::mesh::Culling;
+
+    /// The corners of the top face of the block at `[4, 4, 4]`, with stone
+    /// at each of `solid` and air everywhere else.
+    fn top_face(solid: &[[i32; 3]]) -> [u8; 4] {
+        let region = ChunkRegion::from_fn(Culling::default(), |pos| {
+            Some(if solid.contains(&pos) {
+                Block::Stone
+            } else {
+                Block::Air
+            })
+        });
+        occlusion(&region, [4, 4, 4], [0, 2, 1], 1)
+    }
+
+    #[test]
+    fn open_faces_are_unoccluded() {
+        assert_eq!(top_face(&[]), [3, 3, 3, 3]);
+        // blocks behind the face don't count
+        assert_eq!(top_face(&[[3, 4, 3], [4, 3, 4]]), [3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn occludes_one_corner() {
+        assert_eq!(top_face(&[[3, 5, 3]]), [2, 3, 3, 3]);
+        assert_eq!(top_face(&[[5, 5, 5]]), [3, 3, 2, 3]);
+    }
+
+    #[test]
+    fn two_sides_fully_occlude_their_corner() {
+        // whether or not the corner itself is solid
+        assert_eq!(top_face(&[[3, 5, 4], [4, 5, 3]]), [0, 2, 3, 2]);
+        assert_eq!(top_face(&[[3, 5, 4], [4, 5, 3], [3, 5, 3]]), [0, 2, 3, 2]);
+    }
+
+    #[test]
+    fn splits_along_the_brighter_diagonal() {
+        // evenly lit quads are split between corners 00 and 11
+        assert_eq!(triangulate([3, 3, 3, 3], false), [0, 1, 2, 2, 3, 0]);
+        assert_eq!(triangulate([3, 3, 3, 3], true), [2, 1, 0, 0, 3, 2]);
+
+        // a dark 00 or 11 corner flips the split to between 01 and 10
+        assert_eq!(triangulate([2, 3, 3, 3], false), [1, 2, 3, 3, 0, 1]);
+        assert_eq!(triangulate([3, 3, 0, 3], true), [3, 2, 1, 1, 0, 3]);
+
+        // as does a bright 01 or 10 corner, but not a tie
+        assert_eq!(triangulate([2, 3, 2, 2], false), [1, 2, 3, 3, 0, 1]);
+        assert_eq!(triangulate([2, 2, 2, 2], false), [0, 1, 2, 2, 3, 0]);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::app::block::Block;
+
+/// How to treat faces that border a chunk which isn't loaded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissingNeighbors {
+    /// Hide the faces, since they'll most likely be covered once the
+    /// neighbor is loaded.
+    Cull,
+    /// Draw the faces, closing off the edges of the world.
+    #[default]
+    Emit,
+}
+
+/// The rules for deciding which faces are hidden by their neighbors.
+///
+/// These are shared by every mesher, so that they agree on which faces to
+/// draw.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Culling {
+    pub missing: MissingNeighbors,
+}
+
+impl Culling {
+    /// Whether the face of `block` touching `neighbor` should be drawn.
+    ///
+    /// `neighbor` is `None` if it lies in a chunk that isn't loaded.
+    pub fn is_visible(self, block: Block, neighbor: Option<Block>) -> bool {
+        match neighbor {
+            _ if block == Block::Air => false,
+            None => self.missing == MissingNeighbors::Emit,
+            // faces between two blocks of the same translucent type (e.g.
+            // water) are hidden too
+            Some(neighbor) => neighbor.is_transparent() && neighbor != block,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::app::mesh::{fast, greedy, ChunkRegion};
+    use crate::renderer::mesh::{ChunkMesh, FACE_GROUPS};
+
+    /// Stone below a pool of water and a pocket of air, with a pillar and
+    /// scattered blocks of stone and water, next to an unloaded neighbor
+    /// along -x.
+    fn region(culling: Culling) -> ChunkRegion {
+        ChunkRegion::from_fn(culling, |[x, y, z]| {
+            let scattered = (x * 7 + y * 13 + z * 31) % 11;
+            Some(match [x, y, z] {
+                [-1, ..] => return None,
+                [20..=22, _, 5..=7] if y < 20 => Block::Stone,
+                _ if y < 8 => Block::Stone,
+                _ if scattered == 0 => Block::Stone,
+                _ if scattered == 1 => Block::Water,
+                [..16, ..12, _] => Block::Water,
+                _ => Block::Air,
+            })
+        })
+    }
+
+    /// Every unit face of every layer, by direction and the corner of the
+    /// face nearest the origin.
+    fn unit_faces(mesh: &ChunkMesh) -> BTreeSet<(usize, usize, [i32; 3])> {
+        let mut faces = BTreeSet::new();
+        for (layer, mesh) in mesh.layers.iter().enumerate() {
+            for group in 0..FACE_GROUPS - 1 {
+                let range =
+                    mesh.directions[group].start as usize..mesh.directions[group].end as usize;
+                for quad in mesh.indices[range].chunks(6) {
+                    let corners: Vec<_> = quad
+                        .iter()
+                        .map(|&i| mesh.vertices[i as usize].position())
+                        .collect();
+                    let min = [0, 1, 2].map(|i| {
+                        corners
+                            .iter()
+                            .map(|c| c[i])
+                            .fold(f32::MAX, f32::min)
+                            .round() as i32
+                    });
+                    let max = [0, 1, 2].map(|i| {
+                        corners
+                            .iter()
+                            .map(|c| c[i])
+                            .fold(f32::MIN, f32::max)
+                            .round() as i32
+                    });
+
+                    // split the quad into unit faces, across its flat axis
+                    let size = [0, 1, 2].map(|i| (max[i] - min[i]).max(1));
+                    for dy in 0..size[1] {
+                        for dz in 0..size[2] {
+                            for dx in 0..size[0] {
+                                let corner = [min[0] + dx, min[1] + dy, min[2] + dz];
+                                assert!(faces.insert((layer, group, corner)), "overlapping faces");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        faces
+    }
+
+    #[test]
+    fn fast_and_greedy_agree() {
+        for missing in [MissingNeighbors::Cull, MissingNeighbors::Emit] {
+            let region = region(Culling { missing });
+            let fast = unit_faces(&fast([0, 0, 0], &region));
+            let greedy = unit_faces(&greedy([0, 0, 0], &region));
+            assert!(!fast.is_empty());
+            assert_eq!(fast, greedy, "meshers disagree with {missing:?} neighbors");
+        }
+    }
+
+    #[test]
+    fn missing_neighbors_decide_border_faces() {
+        let emitted = unit_faces(&greedy([0, 0, 0], &region(Culling::default())));
+        let culled = unit_faces(&greedy(
+            [0, 0, 0],
+            &region(Culling {
+                missing: MissingNeighbors::Cull,
+            }),
+        ));
+        // only faces on the unloaded side differ
+        let extra: Vec<_> = emitted.difference(&culled).collect();
+        assert!(!extra.is_empty());
+        assert!(extra.iter().all(|&&(_, _, [x, _, _])| x == 0));
+    }
+}
@@ -1,255 +1,131 @@
-use std::collections::HashMap;
-
-use crate::app::block::Block;
-use crate::app::chunk::{Chunk, CHUNK_SIZE};
-use crate::app::voxel::VoxelSide;
-use crate::renderer::mesh::{DedupMesh, Mesh};
+use super::ao::{occlusion, triangulate};
+use super::{origin, ChunkRegion};
+use crate::app::chunk::CHUNK_SIZE;
+use crate::app::voxel::{Direction, VoxelSide};
+use crate::renderer::mesh::{ChunkMesh, DedupMesh, Layer};
 use crate::renderer::vertex::Vertex;
 
-pub fn fast(chunk: &Chunk, [ax, ay, az]: [i32; 3], chunks: &HashMap<[i32; 3], Chunk>) -> Mesh {
-    let mut mesh = DedupMesh::new();
-
-    let chunk_above = chunks
-        .get(&[ax, ay + 1, az])
-        .map(|neighbor| {
-            let plane = neighbor.blocks[0];
-            let mut above = [[false; CHUNK_SIZE]; CHUNK_SIZE];
-            for (z, row) in plane.iter().enumerate() {
-                for (x, block) in row.iter().enumerate() {
-                    above[z][x] = block.is_transparent();
-                }
-            }
-            above
-        })
-        .unwrap_or([[true; CHUNK_SIZE]; CHUNK_SIZE]);
-
-    let chunk_below = chunks
-        .get(&[ax, ay - 1, az])
-        .map(|neighbor| {
-            let plane = neighbor.blocks[CHUNK_SIZE - 1];
-            let mut below = [[false; CHUNK_SIZE]; CHUNK_SIZE];
-            for (z, row) in plane.iter().enumerate() {
-                for (x, block) in row.iter().enumerate() {
-                    below[z][x] = block.is_transparent();
-                }
-            }
-            below
-        })
-        .unwrap_or([[true; CHUNK_SIZE]; CHUNK_SIZE]);
-
-    let chunk_left = chunks
-        .get(&[ax - 1, ay, az])
-        .map(|neighbor| {
-            let mut left = [[false; CHUNK_SIZE]; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    left[y][z] = neighbor.blocks[y][z][CHUNK_SIZE - 1].is_transparent();
-                }
-            }
-            left
-        })
-        .unwrap_or([[true; CHUNK_SIZE]; CHUNK_SIZE]);
-
-    let chunk_right = chunks
-        .get(&[ax + 1, ay, az])
-        .map(|neighbor| {
-            let mut right = [[false; CHUNK_SIZE]; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    right[y][z] = neighbor.blocks[y][z][0].is_transparent();
-                }
-            }
-            right
-        })
-        .unwrap_or([[true; CHUNK_SIZE]; CHUNK_SIZE]);
-
-    let chunk_back = chunks
-        .get(&[ax, ay, az - 1])
-        .map(|neighbor| {
-            let mut front = [[false; CHUNK_SIZE]; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for x in 0..CHUNK_SIZE {
-                    front[x][y] = neighbor.blocks[y][CHUNK_SIZE - 1][x].is_transparent();
-                }
-            }
-            front
-        })
-        .unwrap_or([[true; CHUNK_SIZE]; CHUNK_SIZE]);
-
-    let chunk_front = chunks
-        .get(&[ax, ay, az + 1])
-        .map(|neighbor| {
-            let mut back = [[false; CHUNK_SIZE]; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for x in 0..CHUNK_SIZE {
-                    back[x][y] = neighbor.blocks[y][0][x].is_transparent();
-                }
-            }
-            back
-        })
-        .unwrap_or([[true; CHUNK_SIZE]; CHUNK_SIZE]);
-
-    let pos = [ax, ay, az];
+pub fn fast(pos: [i32; 3], region: &ChunkRegion) -> ChunkMesh {
+    let mut meshes = Layer::ALL.map(|_| DedupMesh::new());
 
     mesh_face(
-        &mut mesh,
-        chunk,
+        &mut meshes,
+        region,
         VoxelSide::Top,
-        pos,
-        chunk_above,
         [0, 2, 1],
-        [0.0, 1.0, 0.0],
-        false,
+        Direction::Top,
         false,
     );
 
     mesh_face(
-        &mut mesh,
-        chunk,
+        &mut meshes,
+        region,
         VoxelSide::Bottom,
-        pos,
-        chunk_below,
         [0, 2, 1],
-        [0.0, -1.0, 0.0],
-        true,
+        Direction::Bottom,
         true,
     );
 
     mesh_face(
-        &mut mesh,
-        chunk,
+        &mut meshes,
+        region,
         VoxelSide::Side,
-        pos,
-        chunk_back,
         [0, 1, 2],
-        [0.0, 0.0, -1.0],
-        true,
+        Direction::Back,
         false,
     );
 
     mesh_face(
-        &mut mesh,
-        chunk,
+        &mut meshes,
+        region,
         VoxelSide::Side,
-        pos,
-        chunk_front,
         [0, 1, 2],
-        [0.0, 0.0, 1.0],
-        false,
+        Direction::Front,
         true,
     );
 
     mesh_face(
-        &mut mesh,
-        chunk,
+        &mut meshes,
+        region,
         VoxelSide::Side,
-        pos,
-        chunk_left,
         [1, 2, 0],
-        [-1.0, 0.0, 0.0],
-        true,
+        Direction::Left,
         false,
     );
 
     mesh_face(
-        &mut mesh,
-        chunk,
+        &mut meshes,
+        region,
         VoxelSide::Side,
-        pos,
-        chunk_right,
         [1, 2, 0],
-        [1.0, 0.0, 0.0],
-        false,
+        Direction::Right,
         true,
     );
 
-    mesh.into_mesh()
+    ChunkMesh::new(
+        origin(pos),
+        CHUNK_SIZE as f32,
+        meshes.map(DedupMesh::into_mesh),
+    )
 }
 
 fn mesh_face(
-    mesh: &mut DedupMesh,
-    chunk: &Chunk,
+    meshes: &mut [DedupMesh; 2],
+    region: &ChunkRegion,
     voxel_face: VoxelSide,
-    [xc, yc, zc]: [i32; 3],
-    neighbor: [[bool; CHUNK_SIZE]; CHUNK_SIZE],
     [ai, bi, ci]: [usize; 3],
-    normal: [f32; 3],
-    flip: bool,
+    direction: Direction,
     clockwise: bool,
 ) {
+    let sign = direction.normal()[ci];
     for a in 0..CHUNK_SIZE {
         for b in 0..CHUNK_SIZE {
             let mut pos = [0; 3];
             pos[ai] = a;
             pos[bi] = b;
 
-            let mut transparent = 0u64;
-            transparent |= (flip & neighbor[a][b]) as u64;
-
+            // one bit per visible face, with the first block in the highest bit
             let mut blocks = 0u32;
             for c in 0..CHUNK_SIZE {
                 pos[ci] = c;
-                let [x, y, z] = pos;
-                let block = chunk.blocks[y][z][x];
+                let mut neighbor = pos.map(|i| i as i32);
+                neighbor[ci] += sign;
 
                 blocks <<= 1;
-                blocks |= (block != Block::Air) as u32;
-
-                transparent <<= 1;
-                transparent |= block.is_transparent() as u64;
+                blocks |= region.is_visible(region.block(pos), neighbor) as u32;
             }
 
-            if flip {
-                transparent >>= 1;
-            } else {
-                transparent <<= 1;
-                transparent |= neighbor[a][b] as u64;
-            }
-
-            blocks &= transparent as u32;
-
-            if blocks == 0 {
-                continue;
-            }
-
-            let mut c = CHUNK_SIZE - 1;
             while blocks != 0 {
-                if blocks & 1 == 0 {
-                    blocks >>= 1;
-                    c -= 1;
-                    continue;
-                }
+                // the lowest bit corresponds to the last block
+                let c = CHUNK_SIZE - 1 - blocks.trailing_zeros() as usize;
+                blocks &= blocks - 1;
 
                 pos[ci] = c;
-                let [x, y, z] = pos;
-                let color = chunk.blocks[y][z][x].voxel().faces[voxel_face as usize].color;
-
-                let v = |p, q| {
-                    let mut position = [0.0; 3];
-                    position[ai] = a as f32 + p as f32;
-                    position[bi] = b as f32 + q as f32;
-                    position[ci] = c as f32 + !flip as u8 as f32;
-                    let [x, y, z] = position;
-
-                    Vertex {
-                        position: [
-                            x + xc as f32 * CHUNK_SIZE as f32,
-                            y + yc as f32 * CHUNK_SIZE as f32,
-                            z + zc as f32 * CHUNK_SIZE as f32,
-                        ],
-                        color,
-                        normal,
-                    }
+                let block = region.block(pos);
+                let color = block.color_id(voxel_face);
+                let ao = occlusion(region, pos.map(|i| i as i32), [ai, bi, ci], sign);
+
+                // faces are lit by the block in front of them
+                let mut front = pos.map(|i| i as i32);
+                front[ci] += sign;
+                let light = region.light(front).0;
+
+                let v = |p, q, ao: u8| {
+                    let mut position = [0; 3];
+                    position[ai] = (a + p) as u32;
+                    position[bi] = (b + q) as u32;
+                    position[ci] = (c + (sign > 0) as usize) as u32;
+
+                    Vertex::new(position, direction as u8, ao, light, color)
                 };
 
-                if clockwise {
-                    mesh.vertices([v(1, 1), v(0, 1), v(0, 0), v(0, 0), v(1, 0), v(1, 1)]);
-                } else {
-                    mesh.vertices([v(0, 0), v(0, 1), v(1, 1), v(1, 1), v(1, 0), v(0, 0)]);
-                }
-
-                blocks >>= 1;
-                c -= 1;
+                let corners = [
+                    v(0, 0, ao[0]),
+                    v(0, 1, ao[1]),
+                    v(1, 1, ao[2]),
+                    v(1, 0, ao[3]),
+                ];
+                meshes[block.layer() as usize].quad(direction, corners, triangulate(ao, clockwise));
             }
         }
     }
@@ -1,314 +1,277 @@
-use super::ChunkRegion;
+use super::ao::{occlusion, triangulate};
+use super::{origin, ChunkRegion};
 use crate::app::block::Block;
 use crate::app::chunk::CHUNK_SIZE;
-use crate::app::voxel::VoxelSide;
-use crate::renderer::mesh::Mesh;
+use crate::app::voxel::{Direction, VoxelSide};
+use crate::renderer::mesh::{ChunkMesh, DedupMesh, Layer};
 use crate::renderer::vertex::Vertex;
 
-pub fn greedy([ax, ay, az]: [i32; 3], chunks: ChunkRegion) -> Mesh {
-    let mut mesh = Mesh::new();
-
-    let chunk = chunks.center;
-    let chunk_above = chunks
-        .top
-        .map(|neighbor| {
-            let plane = neighbor.blocks[0];
-            let mut above = [0u32; CHUNK_SIZE];
-            for (z, row) in plane.iter().enumerate() {
-                for block in row {
-                    above[z] <<= 1;
-                    above[z] |= block.is_transparent() as u32;
-                }
-            }
-            above
-        })
-        .unwrap_or([!0; CHUNK_SIZE]);
-
-    let chunk_below = chunks
-        .bot
-        .map(|neighbor| {
-            let plane = neighbor.blocks[CHUNK_SIZE - 1];
-            let mut below = [0u32; CHUNK_SIZE];
-            for (z, row) in plane.iter().enumerate() {
-                for block in row {
-                    below[z] <<= 1;
-                    below[z] |= block.is_transparent() as u32;
-                }
-            }
-            below
-        })
-        .unwrap_or([!0; CHUNK_SIZE]);
-
-    let chunk_back = chunks
-        .front
-        .map(|neighbor| {
-            let blocks = &neighbor.blocks;
-            let mut front = [0u32; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for x in 0..CHUNK_SIZE {
-                    front[y] <<= 1;
-                    front[y] |= blocks[y][CHUNK_SIZE - 1][x].is_transparent() as u32;
-                }
-            }
-            front
-        })
-        .unwrap_or([!0; CHUNK_SIZE]);
-
-    let chunk_front = chunks
-        .back
-        .map(|neighbor| {
-            let blocks = &neighbor.blocks;
-            let mut back = [0u32; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for x in 0..CHUNK_SIZE {
-                    back[y] <<= 1;
-                    back[y] |= blocks[y][0][x].is_transparent() as u32;
-                }
-            }
-            back
-        })
-        .unwrap_or([!0; CHUNK_SIZE]);
-
-    let chunk_left = chunks
-        .left
-        .map(|neighbor| {
-            let blocks = &neighbor.blocks;
-            let mut left = [0u32; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    left[z] <<= 1;
-                    left[z] |= blocks[y][z][CHUNK_SIZE - 1].is_transparent() as u32;
-                }
-            }
-            left
-        })
-        .unwrap_or([!0; CHUNK_SIZE]);
-
-    let chunk_right = chunks
-        .right
-        .map(|neighbor| {
-            let blocks = &neighbor.blocks;
-            let mut right = [0u32; CHUNK_SIZE];
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    right[z] <<= 1;
-                    right[z] |= blocks[y][z][0].is_transparent() as u32;
-                }
-            }
-            right
-        })
-        .unwrap_or([!0; CHUNK_SIZE]);
+pub fn greedy(pos: [i32; 3], region: &ChunkRegion) -> ChunkMesh {
+    let kinds: Vec<_> = Block::ALL
+        .into_iter()
+        .filter(|&block| block != Block::Air)
+        .collect();
+    greedy_blocks(pos, region, &kinds)
+}
+
+/// Greedy mesh only the faces of the given kinds of block.
+pub fn greedy_blocks(pos: [i32; 3], region: &ChunkRegion, kinds: &[Block]) -> ChunkMesh {
+    let mut meshes = Layer::ALL.map(|_| DedupMesh::new());
+
+    let culling = region.culling();
+    let edge = CHUNK_SIZE as i32;
+
+    for &kind in kinds {
+        let mesh = &mut meshes[kind.layer() as usize];
+
+        let chunk_above = border(region, kind, |z, x| [x, edge, z]);
+        let chunk_below = border(region, kind, |z, x| [x, -1, z]);
+        let chunk_back = border(region, kind, |y, x| [x, y, -1]);
+        let chunk_front = border(region, kind, |y, x| [x, y, edge]);
+        let chunk_left = border(region, kind, |z, y| [-1, y, z]);
+        let chunk_right = border(region, kind, |z, y| [edge, y, z]);
 
-    for kind in [Block::Water, Block::Grass, Block::Dirt, Block::Stone] {
         let mut above = chunk_above;
-        for (y, plane) in chunk.blocks.iter().enumerate().rev() {
+        for y in (0..CHUNK_SIZE).rev() {
             let mut bitmap = [0u32; CHUNK_SIZE];
-            let mut transparent = [0u32; CHUNK_SIZE];
-            for (z, row) in plane.iter().enumerate() {
-                for block in row {
+            let mut visible = [0u32; CHUNK_SIZE];
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let block = region.block([x, y, z]);
                     bitmap[z] <<= 1;
-                    bitmap[z] |= (*block == kind) as u32;
+                    bitmap[z] |= (block == kind) as u32;
 
-                    transparent[z] <<= 1;
-                    transparent[z] |= (block.is_transparent() && !kind.is_transparent()) as u32;
+                    visible[z] <<= 1;
+                    visible[z] |= culling.is_visible(kind, Some(block)) as u32;
                 }
             }
 
             mesh_face(
-                &mut mesh,
-                [ax, az, ay],
+                mesh,
+                region,
                 bitmap,
                 kind,
                 VoxelSide::Top,
                 above,
-                y as f32 + 1.0,
+                y,
                 [0, 2, 1],
-                [0.0, -1.0],
-                [0.0, 1.0, 0.0],
+                Direction::Top,
                 false,
             );
 
-            above = transparent;
+            above = visible;
         }
 
         let mut below = chunk_below;
-        for (y, plane) in chunk.blocks.iter().enumerate() {
+        for y in 0..CHUNK_SIZE {
             let mut bitmap = [0u32; CHUNK_SIZE];
-            let mut transparent = [0u32; CHUNK_SIZE];
-            for (z, row) in plane.iter().enumerate() {
-                for block in row {
+            let mut visible = [0u32; CHUNK_SIZE];
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let block = region.block([x, y, z]);
                     bitmap[z] <<= 1;
-                    bitmap[z] |= (*block == kind) as u32;
+                    bitmap[z] |= (block == kind) as u32;
 
-                    transparent[z] <<= 1;
-                    transparent[z] |= (block.is_transparent() && !kind.is_transparent()) as u32;
+                    visible[z] <<= 1;
+                    visible[z] |= culling.is_visible(kind, Some(block)) as u32;
                 }
             }
 
             mesh_face(
-                &mut mesh,
-                [ax, az, ay],
+                mesh,
+                region,
                 bitmap,
                 kind,
                 VoxelSide::Bottom,
                 below,
-                y as f32,
+                y,
                 [0, 2, 1],
-                [0.0, -1.0],
-                [0.0, -1.0, 0.0],
+                Direction::Bottom,
                 true,
             );
 
-            below = transparent;
+            below = visible;
         }
 
-        let blocks = &chunk.blocks;
-
         let mut back = chunk_back;
         for z in 0..CHUNK_SIZE {
             let mut bitmap = [0u32; CHUNK_SIZE];
-            let mut transparent = [0u32; CHUNK_SIZE];
+            let mut visible = [0u32; CHUNK_SIZE];
             for y in 0..CHUNK_SIZE {
                 for x in 0..CHUNK_SIZE {
-                    let block = blocks[y][z][x];
+                    let block = region.block([x, y, z]);
                     bitmap[y] <<= 1;
                     bitmap[y] |= (block == kind) as u32;
 
-                    transparent[y] <<= 1;
-                    transparent[y] |= (block.is_transparent() && !kind.is_transparent()) as u32;
+                    visible[y] <<= 1;
+                    visible[y] |= culling.is_visible(kind, Some(block)) as u32;
                 }
             }
 
             mesh_face(
-                &mut mesh,
-                [ax, ay, az],
+                mesh,
+                region,
                 bitmap,
                 kind,
                 VoxelSide::Side,
                 back,
-                z as f32 - 1.0,
+                z,
                 [0, 1, 2],
-                [0.0, 0.0],
-                [0.0, 0.0, -1.0],
+                Direction::Back,
                 false,
             );
 
-            back = transparent;
+            back = visible;
         }
 
         let mut front = chunk_front;
         for z in (0..CHUNK_SIZE).rev() {
             let mut bitmap = [0u32; CHUNK_SIZE];
-            let mut transparent = [0u32; CHUNK_SIZE];
+            let mut visible = [0u32; CHUNK_SIZE];
             for y in 0..CHUNK_SIZE {
                 for x in 0..CHUNK_SIZE {
-                    let block = blocks[y][z][x];
+                    let block = region.block([x, y, z]);
                     bitmap[y] <<= 1;
                     bitmap[y] |= (block == kind) as u32;
 
-                    transparent[y] <<= 1;
-                    transparent[y] |= (block.is_transparent() && !kind.is_transparent()) as u32;
+                    visible[y] <<= 1;
+                    visible[y] |= culling.is_visible(kind, Some(block)) as u32;
                 }
             }
 
             mesh_face(
-                &mut mesh,
-                [ax, ay, az],
+                mesh,
+                region,
                 bitmap,
                 kind,
                 VoxelSide::Side,
                 front,
-                z as f32,
+                z,
                 [0, 1, 2],
-                [0.0, 0.0],
-                [0.0, 0.0, 1.0],
+                Direction::Front,
                 true,
             );
 
-            front = transparent;
+            front = visible;
         }
 
         let mut left = chunk_left;
         for x in 0..CHUNK_SIZE {
             let mut bitmap = [0u32; CHUNK_SIZE];
-            let mut transparent = [0u32; CHUNK_SIZE];
+            let mut visible = [0u32; CHUNK_SIZE];
             for y in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
-                    let block = blocks[y][z][x];
+                    let block = region.block([x, y, z]);
                     bitmap[z] <<= 1;
                     bitmap[z] |= (block == kind) as u32;
 
-                    transparent[z] <<= 1;
-                    transparent[z] |= (block.is_transparent() && !kind.is_transparent()) as u32;
+                    visible[z] <<= 1;
+                    visible[z] |= culling.is_visible(kind, Some(block)) as u32;
                 }
             }
 
             mesh_face(
-                &mut mesh,
-                [ay, az, ax],
+                mesh,
+                region,
                 bitmap,
                 kind,
                 VoxelSide::Side,
                 left,
-                x as f32,
+                x,
                 [1, 2, 0],
-                [0.0, -1.0],
-                [-1.0, 0.0, 0.0],
+                Direction::Left,
                 false,
             );
 
-            left = transparent;
+            left = visible;
         }
 
         let mut right = chunk_right;
         for xx in (0..CHUNK_SIZE).rev() {
             let mut bitmap = [0u32; CHUNK_SIZE];
-            let mut transparent = [0u32; CHUNK_SIZE];
+            let mut visible = [0u32; CHUNK_SIZE];
             for y in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
-                    let block = blocks[y][z][xx];
+                    let block = region.block([xx, y, z]);
                     bitmap[z] <<= 1;
                     bitmap[z] |= (block == kind) as u32;
 
-                    transparent[z] <<= 1;
-                    transparent[z] |= (block.is_transparent() && !kind.is_transparent()) as u32;
+                    visible[z] <<= 1;
+                    visible[z] |= culling.is_visible(kind, Some(block)) as u32;
                 }
             }
 
             mesh_face(
-                &mut mesh,
-                [ay, az, ax],
+                mesh,
+                region,
                 bitmap,
                 kind,
                 VoxelSide::Side,
                 right,
-                xx as f32 + 1.0,
+                xx,
                 [1, 2, 0],
-                [0.0, -1.0],
-                [1.0, 0.0, 0.0],
+                Direction::Right,
                 true,
             );
 
-            right = transparent;
+            right = visible;
         }
     }
 
-    mesh
+    ChunkMesh::new(
+        origin(pos),
+        CHUNK_SIZE as f32,
+        meshes.map(DedupMesh::into_mesh),
+    )
+}
+
+/// Build a bitmap of where faces of `kind` are visible against the blocks
+/// bordering the chunk.
+///
+/// `at` maps a row and a column to a position just outside the chunk.
+fn border(
+    region: &ChunkRegion,
+    kind: Block,
+    at: impl Fn(i32, i32) -> [i32; 3],
+) -> [u32; CHUNK_SIZE] {
+    let mut plane = [0u32; CHUNK_SIZE];
+    for (row, bits) in plane.iter_mut().enumerate() {
+        for col in 0..CHUNK_SIZE {
+            *bits <<= 1;
+            *bits |= region.is_visible(kind, at(row as i32, col as i32)) as u32;
+        }
+    }
+    plane
 }
 
 fn mesh_face(
-    mesh: &mut Mesh,
-    at: [i32; 3],
+    mesh: &mut DedupMesh,
+    region: &ChunkRegion,
     mut bitmap: [u32; CHUNK_SIZE],
     kind: Block,
     voxel_face: VoxelSide,
     neighbor: [u32; CHUNK_SIZE],
-    c: f32,
+    layer: usize,
     [ai, bi, ci]: [usize; 3],
-    [ao, bo]: [f32; 2],
-    normal: [f32; 3],
+    direction: Direction,
     clockwise: bool,
 ) {
+    let sign = direction.normal()[ci];
+
+    // the ambient occlusion of the face at a given row and column, along
+    // with the light reaching it from the block in front
+    let shading_at = |a: u32, b: usize| {
+        let mut pos = [0; 3];
+        pos[ai] = a as i32;
+        pos[bi] = b as i32;
+        pos[ci] = layer as i32;
+        let occlusion = occlusion(region, pos, [ai, bi, ci], sign);
+        pos[ci] += sign;
+        (occlusion, region.light(pos))
+    };
+
+    let opaque = kind.layer() == Layer::Opaque;
+
     let mut idx = 0;
     while idx < CHUNK_SIZE {
         // mask out any obscured faces
@@ -320,61 +283,59 @@ fn mesh_face(
             continue;
         }
 
-        // determine the start and width of the quad
+        // determine the start and width of the quad; only faces with
+        // identical shading can be merged
         let start = row.leading_zeros();
-        let width = (row << start).leading_ones();
+        let shading = shading_at(start, idx);
+        let run = (row << start).leading_ones();
+        let width = (1..run)
+            .find(|&w| shading_at(start + w, idx) != shading)
+            .unwrap_or(run);
 
         let mask = (!0 << (CHUNK_SIZE as u32 - width)) >> start;
 
         // determine the end (height) of the quad
         let end = bitmap[idx + 1..]
             .iter()
+            .zip(&neighbor[idx + 1..])
+            .enumerate()
             // we don't want to generate meshing for obscured faces, but we
             // also don't want to overcomplicate the meshes by being
-            // pedantic; thus, `row` is not masked with `above`
-            .position(|row| row & mask != mask)
+            // pedantic; thus, `row` is not masked with `above`. faces that
+            // can be seen through are the exception, since covering obscured
+            // ones would draw faces inside of e.g. water
+            .position(|(i, (&row, &neighbor))| {
+                let row = if opaque { row } else { row & neighbor };
+                row & mask != mask
+                    || (start..start + width).any(|a| shading_at(a, idx + 1 + i) != shading)
+            })
             // + idx + 1: because we're starting from idx + 1
             .map(|end| end + idx + 1)
             .unwrap_or(CHUNK_SIZE);
 
-        // create the quad
-        let color = kind.voxel().faces[voxel_face as usize].color;
-        let v = |a: u32, b: u32| {
-            let mut position = [0.0; 3];
-            position[ai] = a as f32 + ao + at[0] as f32 * CHUNK_SIZE as f32;
-            position[bi] = b as f32 + bo + at[1] as f32 * CHUNK_SIZE as f32;
-            position[ci] = c + at[2] as f32 * CHUNK_SIZE as f32;
-            position[2] += 1.0;
-            Vertex {
-                position,
-                color,
-                normal,
-            }
+        // create the quad; texture coordinates follow the position in
+        // blocks, so textures repeat across it instead of stretching
+        let (occluded, light) = shading;
+        let color = kind.color_id(voxel_face);
+        let v = |a: u32, b: u32, occlusion: u8| {
+            let mut position = [0; 3];
+            position[ai] = a;
+            position[bi] = b;
+            position[ci] = layer as u32 + (sign > 0) as u32;
+            Vertex::new(position, direction as u8, occlusion, light.0, color)
         };
 
         let depth = end as u32 - idx as u32;
         let a = start;
         let b = idx as u32;
 
-        if clockwise {
-            mesh.vertices([
-                v(a + width, b + depth),
-                v(a, b + depth),
-                v(a, b),
-                v(a, b),
-                v(a + width, b),
-                v(a + width, b + depth),
-            ]);
-        } else {
-            mesh.vertices([
-                v(a, b),
-                v(a, b + depth),
-                v(a + width, b + depth),
-                v(a + width, b + depth),
-                v(a + width, b),
-                v(a, b),
-            ]);
-        }
+        let corners = [
+            v(a, b, occluded[0]),
+            v(a, b + depth, occluded[1]),
+            v(a + width, b + depth, occluded[2]),
+            v(a + width, b, occluded[3]),
+        ];
+        mesh.quad(direction, corners, triangulate(occluded, clockwise));
 
         // clear the bits we already covered
         for row in &mut bitmap[idx..end] {
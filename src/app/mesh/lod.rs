@@ -0,0 +1,242 @@
+use super::cull::{Culling, MissingNeighbors};
+use super::{greedy, smooth_scaled, ChunkRegion, REGION_SIZE};
+use crate::app::block::Block;
+use crate::app::chunk::CHUNK_SIZE;
+use crate::app::light::Light;
+use crate::renderer::mesh::{Mesh, Mesher, LOD_LEVELS};
+
+/// Mesh each level of detail of a chunk, from half resolution down.
+///
+/// Each level is the chunk [downsampled](ChunkRegion::downsample) and greedy
+/// meshed, or smooth meshed if `mesher` is [`Mesher::Smooth`]. Its neighbors
+/// are left out, so that the faces along the chunk's border are always
+/// emitted; these act as skirts, hiding the cracks left where neighbors are
+/// drawn at a different level.
+pub fn lods(mesher: Mesher, pos: [i32; 3], region: &ChunkRegion) -> Vec<[Mesh; 2]> {
+    (1..=LOD_LEVELS)
+        .map(|level| {
+            let factor = 1 << level;
+            let region = region.downsample(factor);
+            match mesher {
+                Mesher::Smooth => smooth_scaled(pos, &region, factor).layers,
+                Mesher::Fast | Mesher::Greedy => greedy(pos, &region).layers,
+            }
+        })
+        .collect()
+}
+
+impl ChunkRegion {
+    /// Merge each `factor`-sized cube of blocks in the center chunk into a
+    /// single block, leaving out the neighbors' blocks.
+    ///
+    /// The neighbors' light is kept, for the faces along the border. Each
+    /// cell of the border takes the light at the center of the cube it
+    /// borders, which is also where the smooth mesher places the cube's
+    /// sample.
+    ///
+    /// A cube becomes solid if at least half of it is, taking on the most
+    /// common solid block in the highest layer it has any; the surface is
+    /// what's seen from a distance. Otherwise, it's water if there's at least
+    /// as much water as air.
+    pub fn downsample(&self, factor: usize) -> ChunkRegion {
+        debug_assert!(
+            CHUNK_SIZE.is_multiple_of(factor),
+            "factor must divide the chunk size"
+        );
+
+        let mut blocks = Box::new([[[None; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]);
+        let mut light = Box::new([[[Light::SKY; REGION_SIZE]; REGION_SIZE]; REGION_SIZE]);
+        let cells = CHUNK_SIZE / factor;
+
+        for cy in 0..cells {
+            for cz in 0..cells {
+                for cx in 0..cells {
+                    let cube = [cx, cy, cz].map(|c| c * factor..(c + 1) * factor);
+                    let block = self.representative(cube.clone().map(|c| c.start), factor);
+
+                    // the cube is as bright as its brightest block
+                    let mut brightest = Light::DARK;
+                    for y in cube[1].clone() {
+                        for z in cube[2].clone() {
+                            for x in cube[0].clone() {
+                                brightest = brightest.max(self.light[y + 1][z + 1][x + 1]);
+                            }
+                        }
+                    }
+
+                    for y in cube[1].clone() {
+                        for z in cube[2].clone() {
+                            for x in cube[0].clone() {
+                                blocks[y + 1][z + 1][x + 1] = Some(block);
+                                light[y + 1][z + 1][x + 1] = brightest;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // the block at the center of the cube a coordinate lies in, for those
+        // within the chunk
+        let center = |c: i32| {
+            if (0..CHUNK_SIZE as i32).contains(&c) {
+                let factor = factor as i32;
+                c / factor * factor + factor / 2
+            } else {
+                c
+            }
+        };
+        let outside = |c: i32| c < 0 || c >= CHUNK_SIZE as i32;
+
+        for y in -1..=CHUNK_SIZE as i32 {
+            for z in -1..=CHUNK_SIZE as i32 {
+                for x in -1..=CHUNK_SIZE as i32 {
+                    if [x, y, z].into_iter().any(outside) {
+                        let [ly, lz, lx] = [y, z, x].map(|c| (c + 1) as usize);
+                        light[ly][lz][lx] = self.light([x, y, z].map(center));
+                    }
+                }
+            }
+        }
+
+        ChunkRegion {
+            blocks,
+            light,
+            culling: Culling {
+                missing: MissingNeighbors::Emit,
+            },
+        }
+    }
+
+    /// Pick the block representing the cube of `size` blocks from its lowest
+    /// corner.
+    fn representative(&self, [mx, my, mz]: [usize; 3], size: usize) -> Block {
+        let mut solid = 0;
+        let mut water = 0;
+        // counts of each solid block in the highest layer with any
+        let mut surface: Vec<(Block, usize)> = Vec::new();
+        let mut surface_y = None;
+
+        for y in my..my + size {
+            for z in mz..mz + size {
+                for x in mx..mx + size {
+                    let block = self.block([x, y, z]);
+                    if block == Block::Water {
+                        water += 1;
+                    } else if !block.is_transparent() {
+                        solid += 1;
+                        if surface_y != Some(y) {
+                            surface.clear();
+                            surface_y = Some(y);
+                        }
+
+                        match surface.iter_mut().find(|(kind, _)| *kind == block) {
+                            Some((_, count)) => *count += 1,
+                            None => surface.push((block, 1)),
+                        }
+                    }
+                }
+            }
+        }
+
+        let volume = size * size * size;
+        if solid * 2 >= volume {
+            let (most_common, _) = surface.into_iter().max_by_key(|&(_, count)| count).unwrap();
+            most_common
+        } else if water * 2 >= volume - solid {
+            Block::Water
+        } else {
+            Block::Air
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A region with `block` everywhere and the given light, including in
+    /// the neighbors.
+    fn region(block: Block, light: impl Fn([i32; 3]) -> Light) -> ChunkRegion {
+        let mut region = ChunkRegion::from_fn(Culling::default(), |_| Some(block));
+        for y in -1..=CHUNK_SIZE as i32 {
+            for z in -1..=CHUNK_SIZE as i32 {
+                for x in -1..=CHUNK_SIZE as i32 {
+                    let [y, z, x] = [y, z, x].map(|c| (c + 1) as usize);
+                    region.light[y][z][x] = light([x, y, z].map(|c| c as i32 - 1));
+                }
+            }
+        }
+        region
+    }
+
+    #[test]
+    fn uniform_regions_downsample_to_themselves() {
+        for block in [Block::Air, Block::Water, Block::Stone] {
+            let region = region(block, |_| Light(0x93));
+            for factor in [2, 4, 8] {
+                let downsampled = region.downsample(factor);
+                for y in -1..=CHUNK_SIZE as i32 {
+                    for z in -1..=CHUNK_SIZE as i32 {
+                        for x in -1..=CHUNK_SIZE as i32 {
+                            let pos = [x, y, z];
+                            let inside = pos.iter().all(|&c| (0..CHUNK_SIZE as i32).contains(&c));
+                            let expected = inside.then_some(block);
+                            assert_eq!(downsampled.get(pos), expected, "{block:?} at {pos:?}");
+                            assert_eq!(downsampled.light(pos), Light(0x93));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn border_light_matches_the_neighbor_cells() {
+        // a different light level at every position along each face
+        let region = region(Block::Air, |[x, y, z]| {
+            Light(((x * 3 + y * 5 + z * 7).rem_euclid(16) * 17) as u8)
+        });
+
+        let factor = 4;
+        let downsampled = region.downsample(factor);
+        let n = CHUNK_SIZE as i32;
+        let center = |c: i32| c / factor as i32 * factor as i32 + factor as i32 / 2;
+        for a in 0..n {
+            for b in 0..n {
+                let [ca, cb] = [center(a), center(b)];
+                for (border, neighbor) in [
+                    ([-1, a, b], [-1, ca, cb]),
+                    ([n, a, b], [n, ca, cb]),
+                    ([a, -1, b], [ca, -1, cb]),
+                    ([a, n, b], [ca, n, cb]),
+                    ([a, b, -1], [ca, cb, -1]),
+                    ([a, b, n], [ca, cb, n]),
+                ] {
+                    assert_eq!(
+                        downsampled.light(border),
+                        region.light(neighbor),
+                        "at {border:?}"
+                    );
+                }
+            }
+        }
+
+        // corners have nothing to sample but themselves
+        assert_eq!(downsampled.light([-1, n, -1]), region.light([-1, n, -1]));
+    }
+
+    #[test]
+    fn cubes_take_their_surface_block() {
+        // grass over stone, more than half solid
+        let region = ChunkRegion::from_fn(Culling::default(), |[_, y, _]| {
+            Some(match y.rem_euclid(4) {
+                0 | 1 => Block::Stone,
+                2 => Block::Grass,
+                _ => Block::Air,
+            })
+        });
+        let downsampled = region.downsample(4);
+        assert_eq!(downsampled.get([0, 0, 0]), Some(Block::Grass));
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::renderer::mesh::{MeshStats, Mesher};
+
+/// How much each new sample contributes to the running averages.
+const SMOOTHING: f64 = 0.1;
+
+/// Decides whether a chunk is worth re-meshing with the greedy mesher once
+/// it's been meshed with the fast one.
+///
+/// Rather than relying on a fixed vertex count, the policy keeps running
+/// averages of how many vertices greedy meshing saves and how long it takes,
+/// and only schedules chunks whose expected savings justify the expected cost.
+/// The averages are kept apart for chunks holding different numbers of kinds
+/// of block, which greedy meshing merges very differently.
+#[derive(Debug, Clone)]
+pub struct MeshPolicy {
+    /// The smallest fraction of vertices greedy meshing must be expected to
+    /// save for it to be worth running.
+    pub min_savings: f64,
+    /// The longest greedy meshing is expected to take for a single chunk,
+    /// so that one complex chunk doesn't hold up the rest.
+    pub budget: Duration,
+    /// After this many chunks in a row are rejected, greedy mesh one anyway
+    /// to keep the estimates up-to-date.
+    pub explore_every: u32,
+
+    /// The estimates for chunks by how many kinds of block they hold.
+    by_kinds: HashMap<usize, Estimate>,
+    /// The estimates across every chunk, for kinds of chunk not yet greedy
+    /// meshed.
+    overall: Estimate,
+    rejected: u32,
+}
+
+/// Running averages of what greedy meshing saves and costs.
+#[derive(Debug, Default, Clone, Copy)]
+struct Estimate {
+    /// Greedy vertex count as a fraction of the fast vertex count.
+    ratio: f64,
+    /// Greedy meshing time in seconds per fast vertex.
+    cost: f64,
+    samples: u64,
+}
+
+impl Estimate {
+    fn record(&mut self, ratio: f64, cost: f64) {
+        if self.samples == 0 {
+            (self.ratio, self.cost) = (ratio, cost);
+        } else {
+            self.ratio += (ratio - self.ratio) * SMOOTHING;
+            self.cost += (cost - self.cost) * SMOOTHING;
+        }
+        self.samples += 1;
+    }
+}
+
+impl MeshPolicy {
+    pub fn new() -> Self {
+        Self {
+            min_savings: 0.2,
+            budget: Duration::from_millis(20),
+            explore_every: 16,
+
+            by_kinds: HashMap::new(),
+            overall: Estimate::default(),
+            rejected: 0,
+        }
+    }
+
+    /// The estimates for chunks holding `kinds` kinds of block, falling back
+    /// on those for every chunk.
+    fn estimate(&self, kinds: usize) -> Option<Estimate> {
+        let estimate = self.by_kinds.get(&kinds).copied();
+        [estimate, Some(self.overall)]
+            .into_iter()
+            .flatten()
+            .find(|estimate| estimate.samples > 0)
+    }
+
+    /// Decide whether to greedy mesh a chunk holding `kinds` kinds of block,
+    /// given the stats of its fast mesh.
+    pub fn prefer_greedy(&mut self, fast: &MeshStats, kinds: usize) -> bool {
+        if fast.mesher != Mesher::Fast || fast.vertices == 0 {
+            return false;
+        }
+
+        let Some(estimate) = self.estimate(kinds) else {
+            // nothing to go on yet
+            return true;
+        };
+
+        let savings = 1.0 - estimate.ratio;
+        let time = Duration::from_secs_f64(estimate.cost * fast.vertices as f64);
+        if savings >= self.min_savings && time <= self.budget {
+            self.rejected = 0;
+            return true;
+        }
+
+        self.rejected += 1;
+        if self.rejected >= self.explore_every {
+            self.rejected = 0;
+            return true;
+        }
+
+        false
+    }
+
+    /// Record the outcome of greedy meshing a chunk holding `kinds` kinds of
+    /// block that was previously fast meshed.
+    pub fn record(&mut self, fast: &MeshStats, greedy: &MeshStats, kinds: usize) {
+        if fast.mesher != Mesher::Fast || greedy.mesher != Mesher::Greedy || fast.vertices == 0 {
+            return;
+        }
+
+        let ratio = greedy.vertices as f64 / fast.vertices as f64;
+        let cost = greedy.time.as_secs_f64() / fast.vertices as f64;
+
+        self.by_kinds.entry(kinds).or_default().record(ratio, cost);
+        self.overall.record(ratio, cost);
+    }
+
+    /// The expected fraction of vertices saved by greedy meshing a chunk
+    /// holding `kinds` kinds of block, if known.
+    pub fn expected_savings(&self, kinds: usize) -> Option<f64> {
+        self.estimate(kinds).map(|estimate| 1.0 - estimate.ratio)
+    }
+
+    /// The expected time to greedy mesh a chunk holding `kinds` kinds of
+    /// block with the given number of fast vertices, if known.
+    pub fn expected_time(&self, vertices: usize, kinds: usize) -> Option<Duration> {
+        self.estimate(kinds)
+            .map(|estimate| Duration::from_secs_f64(estimate.cost * vertices as f64))
+    }
+
+    /// The number of greedy meshes the estimates are based on.
+    pub fn samples(&self) -> u64 {
+        self.overall.samples
+    }
+}
+
+impl Default for MeshPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(mesher: Mesher, vertices: usize) -> MeshStats {
+        MeshStats {
+            vertices,
+            mesher,
+            time: Duration::from_micros(vertices as u64),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decides_by_kinds_of_block() {
+        let mut policy = MeshPolicy::new();
+        // plain chunks merge well, and mixed ones hardly at all
+        policy.record(&stats(Mesher::Fast, 1000), &stats(Mesher::Greedy, 200), 1);
+        policy.record(&stats(Mesher::Fast, 1000), &stats(Mesher::Greedy, 950), 4);
+
+        assert!(policy.prefer_greedy(&stats(Mesher::Fast, 1000), 1));
+        assert!(!policy.prefer_greedy(&stats(Mesher::Fast, 1000), 4));
+        assert_eq!(policy.expected_savings(1), Some(0.8));
+    }
+
+    #[test]
+    fn falls_back_on_every_chunk() {
+        let mut policy = MeshPolicy::new();
+        assert!(policy.prefer_greedy(&stats(Mesher::Fast, 1000), 2));
+
+        policy.record(&stats(Mesher::Fast, 1000), &stats(Mesher::Greedy, 950), 1);
+        assert!(!policy.prefer_greedy(&stats(Mesher::Fast, 1000), 2));
+        assert_eq!(policy.samples(), 1);
+    }
+
+    #[test]
+    fn explores_after_rejecting() {
+        let mut policy = MeshPolicy::new();
+        policy.record(&stats(Mesher::Fast, 1000), &stats(Mesher::Greedy, 950), 1);
+
+        let fast = stats(Mesher::Fast, 1000);
+        let chosen = (0..policy.explore_every)
+            .filter(|_| policy.prefer_greedy(&fast, 1))
+            .count();
+        assert_eq!(chosen, 1);
+    }
+}
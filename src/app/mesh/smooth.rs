@@ -0,0 +1,290 @@
+use super::cull::MissingNeighbors;
+use super::greedy::greedy_blocks;
+use super::ChunkRegion;
+use crate::app::block::Block;
+use crate::app::chunk::CHUNK_SIZE;
+use crate::app::light::Light;
+use crate::app::voxel::VoxelSide;
+use crate::renderer::mesh::{ChunkMesh, DedupMesh, Layer};
+use crate::renderer::vertex::Vertex;
+
+/// Mesh the solid blocks of a chunk as a smooth surface, using surface nets.
+///
+/// Each block is a sample of a density field, solid or not, taken at its
+/// center. Every cell between eight samples that the surface passes through
+/// gets a vertex, placed at the average of where the surface crosses the
+/// cell's edges, and every edge the surface crosses joins the four cells
+/// around it with a quad. Water is still meshed as blocks.
+pub fn smooth(pos: [i32; 3], region: &ChunkRegion) -> ChunkMesh {
+    smooth_scaled(pos, region, 1)
+}
+
+/// Mesh a chunk [downsampled](ChunkRegion::downsample) by `factor` with
+/// [`smooth`], sampling one block from each merged cube.
+pub fn smooth_scaled(pos: [i32; 3], region: &ChunkRegion, factor: usize) -> ChunkMesh {
+    let mut mesh = greedy_blocks(pos, region, &[Block::Water]);
+
+    let grid = Grid::new(region, factor);
+    let emit_missing = region.culling().missing == MissingNeighbors::Emit;
+    let mut surface = DedupMesh::new();
+
+    let n = grid.size as i32;
+    for y in -1..n {
+        for z in -1..n {
+            for x in -1..n {
+                let from = [x, y, z];
+                let from_sample = grid.get(from);
+
+                for axis in 0..3 {
+                    let mut to = from;
+                    to[axis] += 1;
+                    if to[axis] > n {
+                        continue;
+                    }
+                    let to_sample = grid.get(to);
+
+                    // edges are meshed by the chunk holding their lower end,
+                    // unless it's missing, in which case the surface is
+                    // closed off here if need be
+                    let owned = match (from_sample, to_sample) {
+                        (Some(_), Some(_)) => grid.contains(from),
+                        (None, None) => false,
+                        _ => emit_missing && (grid.contains(from) || grid.contains(to)),
+                    };
+                    let from_solid = is_solid(from_sample);
+                    if !owned || from_solid == is_solid(to_sample) {
+                        continue;
+                    }
+
+                    // the four cells around the edge, counterclockwise when
+                    // seen from the end it points towards
+                    let [i, j] = [(axis + 1) % 3, (axis + 2) % 3];
+                    let cell = |di: i32, dj: i32| {
+                        let mut cell = from;
+                        cell[i] -= di;
+                        cell[j] -= dj;
+                        grid.vertex(cell)
+                    };
+                    let corners = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)];
+
+                    // face away from the solid side
+                    let order = if from_solid {
+                        [0, 1, 2, 0, 2, 3]
+                    } else {
+                        [0, 2, 1, 0, 3, 2]
+                    };
+                    surface.oblique_quad(corners, order);
+                }
+            }
+        }
+    }
+
+    mesh.layers[Layer::Opaque as usize] = surface.into_mesh();
+    mesh
+}
+
+fn is_solid(sample: Option<Block>) -> bool {
+    sample.is_some_and(|block| !block.is_transparent())
+}
+
+/// The blocks sampled by the smooth mesher, one per cell of `factor` blocks
+/// along each axis, plus a border of one sample taken from the neighbors.
+struct Grid {
+    /// The number of samples along each axis of the center chunk.
+    size: usize,
+    factor: usize,
+    /// [[[x] z] y], offset by one.
+    samples: Vec<Option<Block>>,
+    /// The light level at each sample, laid out like `samples`.
+    light: Vec<Light>,
+}
+
+impl Grid {
+    fn new(region: &ChunkRegion, factor: usize) -> Self {
+        let size = CHUNK_SIZE / factor;
+        let side = size + 2;
+
+        // the block of the region that a sample is taken from; samples in the
+        // border come from the blocks right next to the chunk
+        let block = |c: usize| match c {
+            0 => -1,
+            c if c == side - 1 => CHUNK_SIZE as i32,
+            c => ((c - 1) * factor) as i32,
+        };
+
+        let mut samples = Vec::with_capacity(side * side * side);
+        let mut light = Vec::with_capacity(side * side * side);
+        for y in 0..side {
+            for z in 0..side {
+                for x in 0..side {
+                    let block = [block(x), block(y), block(z)];
+                    samples.push(region.get(block));
+                    light.push(region.light(block));
+                }
+            }
+        }
+
+        Self {
+            size,
+            factor,
+            samples,
+            light,
+        }
+    }
+
+    fn contains(&self, sample: [i32; 3]) -> bool {
+        sample.iter().all(|&c| c >= 0 && c < self.size as i32)
+    }
+
+    /// Get a sample, where each coordinate ranges from `-1` to `size`.
+    ///
+    /// Returns `None` if it lies in a neighbor that isn't loaded.
+    fn get(&self, sample: [i32; 3]) -> Option<Block> {
+        self.samples[self.index(sample)]
+    }
+
+    fn light(&self, sample: [i32; 3]) -> Light {
+        self.light[self.index(sample)]
+    }
+
+    fn index(&self, sample: [i32; 3]) -> usize {
+        let side = self.size + 2;
+        let [x, y, z] = sample.map(|c| (c + 1) as usize);
+        (y * side + z) * side + x
+    }
+
+    /// Build the vertex of the cell with the given lowest sample.
+    fn vertex(&self, cell: [i32; 3]) -> Vertex {
+        let corners: [[i32; 3]; 8] =
+            std::array::from_fn(|i| [0, 1, 2].map(|axis| cell[axis] + (i >> axis & 1) as i32));
+        let samples = corners.map(|corner| self.get(corner));
+        let density = samples.map(|sample| is_solid(sample) as i32 as f32);
+
+        // average where the surface crosses each of the cell's edges, which
+        // is always halfway along since the density is either 0 or 1
+        let mut sum = [0.0; 3];
+        let mut crossings = 0.0;
+        for a in 0..8 {
+            for axis in 0..3 {
+                let b = a | 1 << axis;
+                if b == a || density[a] == density[b] {
+                    continue;
+                }
+
+                for (i, sum) in sum.iter_mut().enumerate() {
+                    let offset = (a >> i & 1) as f32;
+                    *sum += if i == axis { 0.5 } else { offset };
+                }
+                crossings += 1.0;
+            }
+        }
+
+        // the density rises towards the solid side, so the normal points
+        // down the gradient
+        let mut gradient = [0.0; 3];
+        for (i, &density) in density.iter().enumerate() {
+            for (axis, gradient) in gradient.iter_mut().enumerate() {
+                *gradient += if i >> axis & 1 == 1 {
+                    density
+                } else {
+                    -density
+                } / 4.0;
+            }
+        }
+        let length = gradient.iter().map(|g| g * g).sum::<f32>().sqrt();
+        let normal = if length > 0.0 {
+            gradient.map(|g| -g / length)
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+
+        // sample centers are half a cell into it
+        let scale = self.factor as f32;
+        let position = [0, 1, 2].map(|i| (cell[i] as f32 + sum[i] / crossings + 0.5) * scale);
+
+        // color the vertex after the highest solid block around it
+        let block = (0..8)
+            .rev()
+            .filter(|&i| density[i] > 0.0)
+            .max_by_key(|&i| i >> 1 & 1)
+            .and_then(|i| samples[i])
+            .unwrap_or_default();
+        let side = if normal[1] > 0.7 {
+            VoxelSide::Top
+        } else if normal[1] < -0.7 {
+            VoxelSide::Bottom
+        } else {
+            VoxelSide::Side
+        };
+
+        // the surface is lit by the brightest of the open space around it
+        let light = (0..8)
+            .filter(|&i| density[i] == 0.0)
+            .map(|i| self.light(corners[i]))
+            .fold(Light::DARK, Light::max);
+
+        Vertex::smooth(position, normal, light.0, block.color_id(side))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::app::mesh::Culling;
+
+    fn surface(block: impl Fn([i32; 3]) -> Block) -> ChunkMesh {
+        let region = ChunkRegion::from_fn(Culling::default(), |pos| Some(block(pos)));
+        smooth([0, 0, 0], &region)
+    }
+
+    fn triangles(mesh: &ChunkMesh) -> Vec<[[f32; 3]; 3]> {
+        let surface = &mesh.layers[Layer::Opaque as usize];
+        surface
+            .indices
+            .chunks_exact(3)
+            .map(|tri| [0, 1, 2].map(|i| surface.vertices[tri[i] as usize].position()))
+            .collect()
+    }
+
+    #[test]
+    fn uniform_regions_have_no_surface() {
+        assert!(triangles(&surface(|_| Block::Air)).is_empty());
+        assert!(triangles(&surface(|_| Block::Stone)).is_empty());
+    }
+
+    #[test]
+    fn single_blocks_are_closed() {
+        let mesh = surface(|pos| {
+            if pos == [8, 8, 8] {
+                Block::Stone
+            } else {
+                Block::Air
+            }
+        });
+        let triangles = triangles(&mesh);
+        assert!(!triangles.is_empty());
+
+        for position in triangles.iter().flatten() {
+            assert!(
+                position.iter().all(|c| (8.0..=9.0).contains(c)),
+                "{position:?} is outside the block"
+            );
+        }
+
+        // every edge is shared by exactly two triangles, wound opposite ways
+        let key = |p: [f32; 3]| p.map(|c| (c * 16.0) as i32);
+        let mut edges = HashMap::new();
+        for tri in &triangles {
+            for i in 0..3 {
+                let edge = (key(tri[i]), key(tri[(i + 1) % 3]));
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {a:?} -> {b:?} is used {count} times");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {a:?} -> {b:?} is open");
+        }
+    }
+}
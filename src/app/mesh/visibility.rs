@@ -0,0 +1,121 @@
+use super::ChunkRegion;
+use crate::app::chunk::CHUNK_SIZE;
+use crate::app::voxel::Direction;
+use crate::renderer::occlusion::Visibility;
+
+/// Work out which faces of a chunk can see each other, by flood-filling each
+/// connected group of transparent blocks and connecting every face it
+/// touches.
+pub fn visibility(region: &ChunkRegion) -> Visibility {
+    const N: usize = CHUNK_SIZE;
+
+    let mut seen = vec![false; N * N * N];
+    let index = |[x, y, z]: [usize; 3]| (y * N + z) * N + x;
+
+    let mut visibility = Visibility::NONE;
+    let mut stack = Vec::new();
+
+    for y in 0..N {
+        for z in 0..N {
+            for x in 0..N {
+                if seen[index([x, y, z])] || !region.block([x, y, z]).is_transparent() {
+                    continue;
+                }
+
+                seen[index([x, y, z])] = true;
+                stack.push([x, y, z]);
+
+                // the faces of the chunk this group touches
+                let mut faces = 0u8;
+                while let Some(pos) = stack.pop() {
+                    for direction in Direction::ALL {
+                        let normal = direction.normal();
+                        let next = [0, 1, 2].map(|i| pos[i] as i32 + normal[i]);
+
+                        if next.iter().any(|&c| c < 0 || c >= N as i32) {
+                            faces |= 1 << direction as u8;
+                            continue;
+                        }
+
+                        let next = next.map(|c| c as usize);
+                        if !seen[index(next)] && region.block(next).is_transparent() {
+                            seen[index(next)] = true;
+                            stack.push(next);
+                        }
+                    }
+                }
+
+                for a in Direction::ALL {
+                    for b in Direction::ALL {
+                        if faces & 1 << a as u8 != 0 && faces & 1 << b as u8 != 0 {
+                            visibility.connect(a, b);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    visibility
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::block::Block;
+    use crate::app::mesh::Culling;
+
+    /// The visibility of a stone chunk with air wherever `air` holds.
+    fn carved(air: impl Fn([i32; 3]) -> bool) -> Visibility {
+        let region = ChunkRegion::from_fn(Culling::default(), |pos| {
+            Some(if air(pos) { Block::Air } else { Block::Stone })
+        });
+        visibility(&region)
+    }
+
+    /// Every pair of faces that can see each other.
+    fn pairs(visibility: Visibility) -> Vec<(Direction, Direction)> {
+        let mut pairs = Vec::new();
+        for a in Direction::ALL {
+            for b in Direction::ALL {
+                if visibility.connects(a, b) {
+                    pairs.push((a, b));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn sealed_chunks_hide_everything() {
+        assert_eq!(carved(|_| false), Visibility::NONE);
+        // a pocket of air that doesn't reach any face
+        assert_eq!(
+            carved(|pos| pos.iter().all(|c| (4..12).contains(c))),
+            Visibility::NONE
+        );
+    }
+
+    #[test]
+    fn open_chunks_hide_nothing() {
+        assert_eq!(carved(|_| true), Visibility::ALL);
+    }
+
+    #[test]
+    fn tunnels_connect_their_ends() {
+        use Direction::*;
+
+        let straight = carved(|[_, y, z]| y == 8 && z == 8);
+        assert_eq!(
+            pairs(straight),
+            [(Left, Left), (Left, Right), (Right, Left), (Right, Right)]
+        );
+
+        // along x to the middle, then up to the top
+        let bent = carved(|[x, y, z]| z == 8 && (y == 8 && x <= 8 || x == 8 && y >= 8));
+        assert_eq!(
+            pairs(bent),
+            [(Top, Top), (Top, Left), (Left, Top), (Left, Left)]
+        );
+    }
+}
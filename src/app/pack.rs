@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use log::warn;
+use serde::Deserialize;
+
+use super::block::{self, Block};
+use super::voxel::VoxelSide;
+use crate::renderer::{shader::Shaders, Material};
+
+/// The file defining how blocks look.
+const BLOCKS: &str = "blocks.toml";
+/// The directory of block textures.
+const TEXTURES: &str = "textures";
+/// The directory of files replacing the renderer's shaders.
+const SHADERS: &str = "shaders";
+/// The table colors are looked up in when color grading.
+const COLOR_GRADING: &str = "color_grading.png";
+
+/// How often a watched pack is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Which kinds of resources changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Changes {
+    pub blocks: bool,
+    pub textures: bool,
+    pub shaders: bool,
+    pub color_grading: bool,
+}
+
+impl Changes {
+    /// Everything, for loading a pack for the first time.
+    pub const ALL: Changes = Changes {
+        blocks: true,
+        textures: true,
+        shaders: true,
+        color_grading: true,
+    };
+
+    pub fn any(self) -> bool {
+        self.blocks || self.textures || self.shaders || self.color_grading
+    }
+}
+
+/// A directory of resources laid over the game's built-in ones:
+///
+/// - `blocks.toml`, how each side of each block looks, by block name
+/// - `textures/`, the PNGs block faces refer to by file name
+/// - `shaders/`, WGSL files replacing the renderer's files of the same name,
+///   or for them to include
+/// - `color_grading.png`, the table colors are looked up in, as laid out for
+///   [`Renderer::set_color_grading`](crate::renderer::Renderer::set_color_grading)
+///
+/// Any of them can be left out.
+pub struct ResourcePack {
+    root: PathBuf,
+    /// A directory to read the built-in shaders from, in place of the copies
+    /// built into the game, so that they can be edited as it runs.
+    shader_source: Option<PathBuf>,
+    /// Whether to check the pack for changes as the game runs.
+    pub watch: bool,
+    /// When each file in the pack was last modified, as of the last check.
+    modified: HashMap<PathBuf, SystemTime>,
+    since_check: Duration,
+}
+
+/// How a block looks, overriding its built-in look. `all` applies to every
+/// side, with `top`, `side` and `bottom` applied over it. Textures that
+/// aren't in the pack are left out.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockDefinition {
+    all: Option<FaceDefinition>,
+    top: Option<FaceDefinition>,
+    side: Option<FaceDefinition>,
+    bottom: Option<FaceDefinition>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceDefinition {
+    color: Option<[f32; 4]>,
+    texture: Option<String>,
+}
+
+impl ResourcePack {
+    /// Open the pack at `root`, watching it in debug builds.
+    pub fn new(root: impl Into<PathBuf>, shader_source: Option<PathBuf>) -> Self {
+        let mut pack = Self {
+            root: root.into(),
+            shader_source,
+            watch: cfg!(debug_assertions),
+            modified: HashMap::new(),
+            since_check: Duration::ZERO,
+        };
+        pack.modified = pack.scan();
+        pack
+    }
+
+    pub fn textures(&self) -> PathBuf {
+        self.root.join(TEXTURES)
+    }
+
+    /// Every block's material, indexed by [`Block::color_id`], with the
+    /// pack's definitions applied over the built-in ones.
+    pub fn palette(&self) -> Result<Vec<Material>> {
+        let mut palette = block::palette();
+
+        let path = self.root.join(BLOCKS);
+        if !path.exists() {
+            return Ok(palette);
+        }
+        let text = fs::read_to_string(&path).with_context(|| format!("reading {path:?}"))?;
+        let definitions: HashMap<String, BlockDefinition> =
+            toml::from_str(&text).with_context(|| format!("parsing {path:?}"))?;
+
+        let textures = self.textures();
+        for (name, definition) in definitions {
+            let Some(block) = Block::ALL.into_iter().find(|block| block.name() == name) else {
+                warn!("No block named {name:?}");
+                continue;
+            };
+
+            for (side, face) in [
+                (VoxelSide::Top, &definition.top),
+                (VoxelSide::Side, &definition.side),
+                (VoxelSide::Bottom, &definition.bottom),
+            ] {
+                let material = &mut palette[block.color_id(side) as usize];
+                for face in [&definition.all, face].into_iter().flatten() {
+                    if let Some(color) = face.color {
+                        material.color = color;
+                    }
+                    if let Some(texture) = &face.texture {
+                        // keep the look the face had if there's no such texture
+                        if textures.join(texture).with_extension("png").is_file() {
+                            material.texture = Some(texture.clone());
+                        } else {
+                            warn!("No texture named {texture:?} in {textures:?}, for {name:?}");
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(palette)
+    }
+
+    /// The renderer's shaders, with the pack's files in place of the
+    /// built-in ones.
+    pub fn shaders(&self) -> Result<Shaders> {
+        let mut shaders = Shaders::default();
+        if let Some(dir) = &self.shader_source {
+            shaders.read_dir(dir)?;
+        }
+
+        let dir = self.root.join(SHADERS);
+        if dir.is_dir() {
+            shaders.read_dir(&dir)?;
+        }
+
+        Ok(shaders)
+    }
+
+    /// The pack's color grading table, if it has one.
+    pub fn color_grading(&self) -> Result<Option<RgbaImage>> {
+        let path = self.root.join(COLOR_GRADING);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let image = image::open(&path).with_context(|| format!("loading {path:?}"))?;
+        Ok(Some(image.to_rgba8()))
+    }
+
+    /// Advance time by `dt`, checking what's changed every so often if the
+    /// pack is being watched.
+    pub fn update(&mut self, dt: Duration) -> Changes {
+        if !self.watch {
+            return Changes::default();
+        }
+
+        self.since_check += dt;
+        if self.since_check < POLL_INTERVAL {
+            return Changes::default();
+        }
+        self.since_check = Duration::ZERO;
+        self.poll()
+    }
+
+    /// Check what's changed since the last check, including files being
+    /// added or removed.
+    pub fn poll(&mut self) -> Changes {
+        let modified = self.scan();
+        let mut changes = Changes::default();
+
+        for path in modified.keys().chain(self.modified.keys()) {
+            if modified.get(path) == self.modified.get(path) {
+                continue;
+            }
+            if self.in_shader_source(path) {
+                changes.shaders = true;
+                continue;
+            }
+
+            let kind = path
+                .strip_prefix(&self.root)
+                .ok()
+                .and_then(|path| path.components().next())
+                .and_then(|kind| kind.as_os_str().to_str());
+            match kind {
+                Some(BLOCKS) => changes.blocks = true,
+                Some(TEXTURES) => changes.textures = true,
+                Some(SHADERS) => changes.shaders = true,
+                Some(COLOR_GRADING) => changes.color_grading = true,
+                _ => {}
+            }
+        }
+
+        self.modified = modified;
+        changes
+    }
+
+    /// Find when each file in the pack was last modified.
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let mut modified = HashMap::new();
+        let mut add = |path: PathBuf| {
+            if let Ok(time) = fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+                modified.insert(path, time);
+            }
+        };
+
+        add(self.root.join(BLOCKS));
+        add(self.root.join(COLOR_GRADING));
+        let dirs = [TEXTURES, SHADERS].map(|dir| self.root.join(dir));
+        for dir in dirs.iter().chain(&self.shader_source) {
+            if let Ok(entries) = fs::read_dir(dir) {
+                entries.flatten().for_each(|entry| add(entry.path()));
+            }
+        }
+
+        modified
+    }
+
+    fn in_shader_source(&self, path: &Path) -> bool {
+        self.shader_source
+            .as_ref()
+            .is_some_and(|dir| path.starts_with(dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory in the system's temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("voxers-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join(TEXTURES)).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            fs::write(self.0.join(path), contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn material(palette: &[Material], block: Block, side: VoxelSide) -> &Material {
+        &palette[block.color_id(side) as usize]
+    }
+
+    #[test]
+    fn applies_sides_over_all() {
+        let dir = TempDir::new("merge");
+        dir.write("textures/rock.png", "");
+        dir.write("textures/moss.png", "");
+        dir.write(
+            BLOCKS,
+            r#"
+            [stone]
+            all = { color = [1.0, 0.0, 0.0, 1.0], texture = "rock" }
+            top = { texture = "moss" }
+            bottom = { color = [0.0, 0.0, 1.0, 1.0] }
+            "#,
+        );
+
+        let palette = ResourcePack::new(&dir.0, None).palette().unwrap();
+        let top = material(&palette, Block::Stone, VoxelSide::Top);
+        assert_eq!(top.color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(top.texture.as_deref(), Some("moss"));
+
+        let side = material(&palette, Block::Stone, VoxelSide::Side);
+        assert_eq!(side.color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(side.texture.as_deref(), Some("rock"));
+
+        let bottom = material(&palette, Block::Stone, VoxelSide::Bottom);
+        assert_eq!(bottom.color, [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(bottom.texture.as_deref(), Some("rock"));
+
+        // other blocks keep their built-in look
+        let builtin = block::palette();
+        let dirt = Block::Dirt.color_id(VoxelSide::Side) as usize;
+        assert_eq!(palette[dirt].color, builtin[dirt].color);
+    }
+
+    #[test]
+    fn skips_unknown_names() {
+        let dir = TempDir::new("unknown");
+        dir.write(
+            BLOCKS,
+            r#"
+            marble = { all = { color = [1.0, 1.0, 1.0, 1.0] } }
+            dirt = { all = { color = [0.0, 1.0, 0.0, 1.0], texture = "missing" } }
+            "#,
+        );
+
+        let palette = ResourcePack::new(&dir.0, None).palette().unwrap();
+        let builtin = block::palette();
+        let dirt = material(&palette, Block::Dirt, VoxelSide::Side);
+        assert_eq!(dirt.color, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(
+            dirt.texture,
+            material(&builtin, Block::Dirt, VoxelSide::Side).texture
+        );
+    }
+
+    #[test]
+    fn loads_without_any_files() {
+        let dir = TempDir::new("empty");
+        let pack = ResourcePack::new(&dir.0, None);
+        assert_eq!(pack.palette().unwrap().len(), block::palette().len());
+        assert!(pack.color_grading().unwrap().is_none());
+    }
+
+    #[test]
+    fn polls_added_and_removed_files() {
+        let dir = TempDir::new("poll");
+        dir.write(BLOCKS, "");
+        let mut pack = ResourcePack::new(&dir.0, None);
+        assert!(!pack.poll().any());
+
+        dir.write("textures/rock.png", "");
+        assert_eq!(
+            pack.poll(),
+            Changes {
+                textures: true,
+                ..Changes::default()
+            }
+        );
+        assert!(!pack.poll().any());
+
+        fs::remove_file(dir.0.join("textures/rock.png")).unwrap();
+        assert_eq!(
+            pack.poll(),
+            Changes {
+                textures: true,
+                ..Changes::default()
+            }
+        );
+
+        fs::remove_file(dir.0.join(BLOCKS)).unwrap();
+        assert_eq!(
+            pack.poll(),
+            Changes {
+                blocks: true,
+                ..Changes::default()
+            }
+        );
+    }
+}
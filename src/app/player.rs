@@ -9,16 +9,14 @@ mod controller;
 use controller::CameraController;
 
 pub struct Player {
-    pub position: [f32; 3],
     pub camera: Camera,
     pub controller: CameraController,
 }
 
 impl Player {
-    pub fn new([x, y, z]: [f32; 3], size: PhysicalSize<u32>) -> Self {
+    pub fn new(size: PhysicalSize<u32>) -> Self {
         let aspect = size.width as f32 / size.height as f32;
         Self {
-            position: [x, y, z],
             camera: Camera::new([0.0, 10.0, 0.0], aspect),
             controller: CameraController::new(25.0, 120.0),
         }
@@ -0,0 +1,122 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use image::{ImageFormat, RgbaImage};
+
+/// Save `image` as a PNG in `dir`, named after the time it's saved at,
+/// returning where it went.
+pub fn save(image: &RgbaImage, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("creating {dir:?}"))?;
+
+    let timestamp = timestamp(SystemTime::now());
+    let (path, file) = create(dir, &timestamp)?;
+
+    let written = image.write_to(&mut BufWriter::new(file), ImageFormat::Png);
+    if written.is_err() {
+        // don't leave a broken screenshot behind
+        let _ = fs::remove_file(&path);
+    }
+    written.with_context(|| format!("saving {path:?}"))?;
+    Ok(path)
+}
+
+/// Create a new PNG in `dir` named `name`, numbering it if there's already
+/// one of that name, e.g. from earlier in the same second.
+fn create(dir: &Path, name: &str) -> Result<(PathBuf, File)> {
+    let mut path = dir.join(format!("{name}.png"));
+    let mut n = 1;
+    loop {
+        // checking and creating in one go, so that nothing else can take the
+        // name in between
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                n += 1;
+                path = dir.join(format!("{name}-{n}.png"));
+            }
+            Err(err) => return Err(err).with_context(|| format!("creating {path:?}")),
+        }
+    }
+}
+
+/// The UTC date and time as `YYYY-MM-DD_HH-MM-SS`, which sorts in order and
+/// can go in a file name anywhere.
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // count from the start of March, so that leap days come last, in eras of
+    // 400 years; see Howard Hinnant's `civil_from_days`
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(seconds: u64) -> String {
+        timestamp(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn formats_the_epoch() {
+        assert_eq!(at(0), "1970-01-01_00-00-00");
+    }
+
+    #[test]
+    fn formats_leap_days() {
+        // 2024-02-29 12:34:56 UTC
+        assert_eq!(at(1_709_210_096), "2024-02-29_12-34-56");
+        assert_eq!(at(1_709_210_096 + 86400), "2024-03-01_12-34-56");
+    }
+
+    #[test]
+    fn formats_year_boundaries() {
+        // 2023-12-31 23:59:59 UTC, and the second after
+        assert_eq!(at(1_704_067_199), "2023-12-31_23-59-59");
+        assert_eq!(at(1_704_067_200), "2024-01-01_00-00-00");
+    }
+
+    #[test]
+    fn numbers_screenshots_taken_together() {
+        let dir = std::env::temp_dir().join(format!("voxers-screenshots-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = (0..3)
+            .map(|_| create(&dir, "shot").unwrap().0)
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["shot.png", "shot-2.png", "shot-3.png"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
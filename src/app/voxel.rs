@@ -5,7 +5,10 @@ pub struct Voxel {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Face {
+    /// The face's color, tinting its texture.
     pub color: [f32; 4],
+    /// The name of the face's texture, if it has one.
+    pub texture: Option<&'static str>,
 }
 
 #[repr(u8)]
@@ -17,7 +20,7 @@ pub enum VoxelSide {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     Top,
     Bottom,
@@ -26,3 +29,37 @@ pub enum Direction {
     Front,
     Back,
 }
+
+impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::Top,
+        Direction::Bottom,
+        Direction::Left,
+        Direction::Right,
+        Direction::Front,
+        Direction::Back,
+    ];
+
+    /// The unit vector pointing in this direction.
+    pub const fn normal(self) -> [i32; 3] {
+        match self {
+            Direction::Top => [0, 1, 0],
+            Direction::Bottom => [0, -1, 0],
+            Direction::Left => [-1, 0, 0],
+            Direction::Right => [1, 0, 0],
+            Direction::Front => [0, 0, 1],
+            Direction::Back => [0, 0, -1],
+        }
+    }
+
+    pub const fn opposite(self) -> Self {
+        match self {
+            Direction::Top => Direction::Bottom,
+            Direction::Bottom => Direction::Top,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::Front => Direction::Back,
+            Direction::Back => Direction::Front,
+        }
+    }
+}
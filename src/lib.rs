@@ -61,15 +61,15 @@ pub async fn run() -> anyhow::Result<()> {
                 state.draw();
             }
             Event::AboutToWait => window.request_redraw(),
-            Event::WindowEvent { window_id, event } if window_id == window.id() => {
-                if !state.mouse_input(&event, dt) {
-                    match event {
-                        WindowEvent::Resized(new_size) => state.renderer.resize(new_size),
-                        WindowEvent::KeyboardInput { event, .. } => {
-                            state.key_input(&event, dt);
-                        }
-                        _ => {}
+            Event::WindowEvent { window_id, event }
+                if window_id == window.id() && !state.mouse_input(&event, dt) =>
+            {
+                match event {
+                    WindowEvent::Resized(new_size) => state.renderer.resize(new_size),
+                    WindowEvent::KeyboardInput { event, .. } => {
+                        state.key_input(&event, dt);
                     }
+                    _ => {}
                 }
             }
             Event::DeviceEvent { event, .. } => {
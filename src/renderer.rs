@@ -1,40 +1,158 @@
-// TODO: consider MDI (multi draw indirect)
+use std::path::Path;
 
-use log::{debug, info, trace};
-use wgpu::util::DeviceExt;
+use anyhow::Context;
+use bytemuck::{Pod, Zeroable};
+use image::RgbaImage;
+use log::{debug, info, trace, warn};
+use vek::{Mat4, Vec3};
+use wgpu::util::{DeviceExt, DrawIndexedIndirectArgs};
 use winit::{dpi::PhysicalSize, window::Window};
 
+pub mod arena;
+pub mod atlas;
 pub mod cached;
 pub mod camera;
+pub mod frustum;
 pub mod light;
 pub mod mesh;
+pub mod mode;
+pub mod occlusion;
+pub mod post;
+pub mod shader;
+pub mod shadow;
+pub mod sky;
 pub mod texture;
 pub mod vertex;
 
-use cached::CachedMesh;
+use atlas::Atlas;
+use cached::{CachedChunk, ChunkArena};
 use camera::{Camera, CameraUniform};
+use frustum::Frustum;
 use light::LightUniform;
-use mesh::Mesh;
+use mesh::{ChunkMesh, Layer, LOD_LEVELS};
+use mode::{PipelineConfig, RenderMode};
+use post::{PostChain, PostEffect, PostSettings, HDR_FORMAT};
+use shader::{Modules, Shaders};
+use shadow::{ShadowMap, ShadowUniform};
+use sky::{Sky, SkyPass};
 use texture::Texture;
-use vertex::Vertex;
+use vertex::{Instance, Vertex};
+
+/// How many chunks were drawn in the last frame, and why the rest weren't.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullStats {
+    /// Every chunk passed to [`Renderer::render`].
+    pub chunks: usize,
+    /// Chunks entirely beyond the view distance, lost in fog.
+    pub distance_culled: usize,
+    /// Chunks entirely outside the camera's frustum.
+    pub frustum_culled: usize,
+    /// Chunks in the frustum, but hidden behind other chunks.
+    pub occlusion_culled: usize,
+    /// Indices skipped because their faces point away from the camera.
+    pub direction_culled: u32,
+    /// Chunks drawn at each level of detail, from full resolution down.
+    pub lods: [usize; LOD_LEVELS + 1],
+    pub drawn: usize,
+    /// Chunks drawn into the shadow map.
+    pub shadow_casters: usize,
+}
+
+/// How one entry of the palette looks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    /// The color, which tints the texture if there is one.
+    pub color: [f32; 4],
+    /// The name of a texture in the atlas.
+    pub texture: Option<String>,
+    /// How brightly the material glows by itself, from 0 to 1, whatever
+    /// light reaches it.
+    pub emission: f32,
+}
+
+/// A [`Material`] as the shaders see it.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct PaletteEntry {
+    color: [f32; 4],
+    /// The texture's layer in the atlas.
+    layer: u32,
+    emission: f32,
+    _padding: [u32; 2],
+}
+
+/// The format of frames drawn offscreen.
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// What frames are drawn to.
+enum Target {
+    /// A window's surface, presented after each frame.
+    Window {
+        surface: wgpu::Surface<'static>,
+        config: wgpu::SurfaceConfiguration,
+    },
+    /// A texture, which frames can be read back from.
+    Offscreen { texture: wgpu::Texture },
+}
 
 pub struct Renderer {
     _instance: wgpu::Instance,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface<'static>,
-    config: wgpu::SurfaceConfiguration,
+    target: Target,
+    /// The format of the frames drawn.
+    format: wgpu::TextureFormat,
     pub size: PhysicalSize<u32>,
 
-    pipelines: [wgpu::RenderPipeline; 2],
+    /// The pipelines each [`Layer`] is drawn with in the current render
+    /// mode, one after another.
+    pipelines: [Vec<wgpu::RenderPipeline>; 2],
+    mode: RenderMode,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    shaders: Shaders,
+    /// The shaders, compiled.
+    modules: Modules,
     depth_texture: Texture,
 
+    /// Holds every cached chunk's mesh.
+    arena: ChunkArena,
+    /// Draw arguments for every chunk layer, rewritten each frame.
+    indirect: wgpu::Buffer,
+    /// Whether the device supports drawing every chunk layer at once with
+    /// [`wgpu::RenderPass::multi_draw_indexed_indirect`].
+    multi_draw: bool,
+
     sun: wgpu::Buffer,
     sun_bind_group: wgpu::BindGroup,
+    /// The direction towards the sun, if it's a directional light.
+    sun_direction: Option<Vec3<f32>>,
+    shadow: ShadowMap,
+    /// What's drawn behind everything else, and fogs the distance.
+    sky: SkyPass,
+    /// Takes each frame from HDR to the target.
+    post: PostChain,
+
+    palette: wgpu::Buffer,
+    palette_bind_group_layout: wgpu::BindGroupLayout,
+    palette_bind_group: wgpu::BindGroup,
+    /// The materials last set, kept to look their textures up again when
+    /// the atlas changes.
+    materials: Vec<Material>,
+    atlas: Atlas,
 
     camera_buffer: wgpu::Buffer,
     camera_uniform: CameraUniform,
     camera_bind_group: wgpu::BindGroup,
+    eye: Vec3<f32>,
+    view_proj: Mat4<f32>,
+    frustum: Frustum,
+    /// Whether to skip chunks that can't be seen through the chunks between
+    /// them and the camera.
+    occlusion_culling: bool,
+    /// How far away chunks start being drawn at lower detail. Each level
+    /// after the first starts at twice the distance of the last.
+    lod_distance: f32,
+    cull_stats: CullStats,
 }
 
 impl Renderer {
@@ -63,15 +181,7 @@ impl Renderer {
             .expect("failed to get adapter");
         debug!("Adapter acquired");
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    required_features: wgpu::Features::POLYGON_MODE_LINE,
-                    required_limits: wgpu::Limits::default(),
-                },
-                None,
-            )
+        let (device, queue) = request_device(&adapter)
             .await
             .expect("failed to get device");
         debug!("Device and queue acquired");
@@ -97,7 +207,71 @@ impl Renderer {
         surface.configure(&device, &config);
         debug!("Surface configured");
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, "Depth texture");
+        let target = Target::Window { surface, config };
+        Self::with_target(instance, device, queue, target, surface_format, size)
+    }
+
+    /// Create a renderer that draws into a texture of the given size rather
+    /// than a window, to read frames back with
+    /// [`render_to_image`](Self::render_to_image).
+    ///
+    /// If `software` is set, only a software adapter will do, so that frames
+    /// come out the same on any machine. Otherwise any adapter will, falling
+    /// back to a software one if there's no other.
+    pub async fn offscreen(size: PhysicalSize<u32>, software: bool) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        debug!("Instance created");
+
+        let mut adapter = None;
+        for force_fallback_adapter in [software, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::None,
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.context("no adapter found")?;
+        debug!("Adapter acquired: {:?}", adapter.get_info());
+
+        let (device, queue) = request_device(&adapter).await?;
+        debug!("Device and queue acquired");
+
+        let texture = create_offscreen(&device, size, OFFSCREEN_FORMAT);
+        let target = Target::Offscreen { texture };
+        Ok(Self::with_target(
+            instance,
+            device,
+            queue,
+            target,
+            OFFSCREEN_FORMAT,
+            size,
+        ))
+    }
+
+    fn with_target(
+        instance: wgpu::Instance,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        target: Target,
+        format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        // base instances pick out each chunk's origin in indirect draws
+        let multi_draw = device.features().contains(MULTI_DRAW_FEATURES);
+        if !multi_draw {
+            info!("Multi-draw indirect unsupported, falling back to a draw per chunk");
+        }
+
+        let depth_texture =
+            Texture::create_depth_texture(&device, [size.width, size.height], "Depth texture");
         debug!("Depth texture created");
 
         let camera_uniform = CameraUniform::new();
@@ -142,233 +316,810 @@ impl Renderer {
 
         let sun_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
                 label: None,
             });
 
-        let sun_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &sun_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: sun.as_entire_binding(),
-            }],
-            label: None,
-        });
-        debug!("Sun initialized");
-
-        let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Vertex Shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                concat!(
-                    include_str!("shaders/common.wgsl"),
-                    include_str!("shaders/vert.wgsl")
-                )
-                .into(),
-            ),
-        });
+        let palette_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("Palette Bind Group Layout"),
+            });
 
-        let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Fragment Shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                concat!(
-                    include_str!("shaders/common.wgsl"),
-                    include_str!("shaders/frag.wgsl")
-                )
-                .into(),
-            ),
-        });
+        let atlas = Atlas::empty(&device, &queue);
+        let (palette, palette_bind_group) = create_palette(
+            &device,
+            &palette_bind_group_layout,
+            &[PaletteEntry::zeroed()],
+            &atlas,
+        );
+        debug!("Palette initialized");
 
+        let shaders = Shaders::default();
+        let modules = shaders
+            .compile(&device)
+            .expect("the built-in shaders are valid");
         debug!("Shaders initialized");
 
-        let fill_render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Fill render"),
-                bind_group_layouts: &[&camera_bind_group_layout, &sun_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let fill_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&fill_render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vert_shader,
-                entry_point: "main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &frag_shader,
-                entry_point: "main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-
-                    blend: None,
-                    // blend: Some(wgpu::BlendState {
-                    // color: wgpu::BlendComponent {
-                    // src_factor: wgpu::BlendFactor::SrcAlpha,
-                    // dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                    // operation: wgpu::BlendOperation::Add,
-                    // },
-                    // alpha: wgpu::BlendComponent::OVER,
-                    // }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let shadow = ShadowMap::new(
+            &device,
+            &camera_bind_group_layout,
+            &palette_bind_group_layout,
+            &modules.vert,
+        );
 
-        let wire_render_pipeline_layout =
+        let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Wire render"),
-                bind_group_layouts: &[&camera_bind_group_layout, &sun_bind_group_layout],
+                label: Some("Render"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &sun_bind_group_layout,
+                    &palette_bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let wire_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let sky = SkyPass::new(&device, &render_pipeline_layout, HDR_FORMAT, &modules.sky);
+        let post = PostChain::new(&device, &queue, size, format, &modules.post);
+
+        let sun_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &sun_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: sun.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadow.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadow.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadow.texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: sky.buffer.as_entire_binding(),
+                },
+            ],
             label: None,
-            layout: Some(&wire_render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vert_shader,
-                entry_point: "main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &frag_shader,
-                entry_point: "main",
-                targets: &[Some(surface_format.into())],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Line,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
         });
+        debug!("Sun initialized");
+
+        let pipelines = create_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &modules,
+            RenderMode::default(),
+        );
         debug!("Pipeline initialized");
 
+        let arena = ChunkArena::new(&device);
+        let indirect = create_indirect(&device, 0);
+        debug!("Chunk arena initialized");
+
         info!("Renderer initialized");
         Self {
             _instance: instance,
             device,
             queue,
-            surface,
-            config,
+            target,
+            format,
             size,
 
-            pipelines: [fill_render_pipeline, wire_render_pipeline],
+            pipelines,
+            mode: RenderMode::default(),
+            render_pipeline_layout,
+            shaders,
+            modules,
             depth_texture,
 
+            arena,
+            indirect,
+            multi_draw,
+
             sun,
             sun_bind_group,
+            sun_direction: None,
+            shadow,
+            sky,
+            post,
+
+            palette,
+            palette_bind_group_layout,
+            palette_bind_group,
+            materials: Vec::new(),
+            atlas,
 
             camera_buffer,
             camera_uniform,
             camera_bind_group,
+            eye: Vec3::zero(),
+            view_proj: Mat4::identity(),
+            frustum: Frustum::default(),
+            occlusion_culling: true,
+            lod_distance: 128.0,
+            cull_stats: CullStats::default(),
+        }
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    /// Whether the device can draw in a render mode; not every device can
+    /// draw wireframes.
+    pub fn supports(&self, mode: RenderMode) -> bool {
+        !mode.wireframe()
+            || self
+                .device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE)
+    }
+
+    /// Draw terrain in another way, rebuilding its pipelines. Modes the
+    /// device doesn't [support](Self::supports) are ignored.
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        if mode == self.mode {
+            return;
+        }
+        if !self.supports(mode) {
+            warn!("Render mode {mode:?} unsupported by this device");
+            return;
+        }
+        self.mode = mode;
+        self.pipelines = create_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.modules,
+            mode,
+        );
+    }
+
+    pub fn shaders(&self) -> &Shaders {
+        &self.shaders
+    }
+
+    /// Rebuild every pipeline from `shaders`.
+    ///
+    /// If they don't compile, the pipelines are left as they were.
+    pub fn set_shaders(&mut self, shaders: Shaders) -> anyhow::Result<()> {
+        if shaders == self.shaders {
+            return Ok(());
+        }
+
+        // naga has already checked the shaders themselves, but not that they
+        // fit the pipelines
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let modules = match shaders.compile(&self.device) {
+            Ok(modules) => modules,
+            Err(err) => {
+                pollster::block_on(self.device.pop_error_scope());
+                return Err(err);
+            }
+        };
+        let pipelines = create_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &modules,
+            self.mode,
+        );
+        let shadow = self.shadow.create_pipeline(&self.device, &modules.vert);
+        let sky = SkyPass::create_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            HDR_FORMAT,
+            &modules.sky,
+        );
+        let post = PostChain::create_pipelines(
+            &self.device,
+            self.post.layout(),
+            self.format,
+            &modules.post,
+        );
+        if let Some(err) = pollster::block_on(self.device.pop_error_scope()) {
+            anyhow::bail!("{err}");
         }
+
+        self.pipelines = pipelines;
+        self.shadow.pipeline = shadow;
+        self.sky.pipeline = sky;
+        self.post.set_pipelines(post);
+        self.shaders = shaders;
+        self.modules = modules;
+        info!("Shaders rebuilt");
+        Ok(())
+    }
+
+    /// Turn one of the shaders' features on or off, rebuilding them.
+    pub fn set_shader_feature(&mut self, name: &str, enabled: bool) -> anyhow::Result<()> {
+        let mut shaders = self.shaders.clone();
+        shaders.set_feature(name, enabled);
+        self.set_shaders(shaders)
+    }
+
+    /// Turn occlusion culling on or off, returning whether it's now on.
+    pub fn toggle_occlusion_culling(&mut self) -> bool {
+        self.occlusion_culling = !self.occlusion_culling;
+        self.occlusion_culling
+    }
+
+    /// How far away chunks start being drawn at lower detail.
+    pub fn lod_distance(&self) -> f32 {
+        self.lod_distance
     }
 
-    pub fn next_pipeline(&mut self) {
-        let [l, r] = &mut self.pipelines;
-        std::mem::swap(l, r);
+    /// Set how far away chunks start being drawn at lower detail.
+    pub fn set_lod_distance(&mut self, distance: f32) {
+        self.lod_distance = distance;
+    }
+
+    /// Set how far from the camera shadows are drawn, in blocks.
+    pub fn set_shadow_distance(&mut self, distance: f32) {
+        self.shadow.distance = distance;
     }
 
     pub fn light(&mut self, light: LightUniform) {
+        self.sun_direction = light.direction().map(Vec3::from);
         self.queue
             .write_buffer(&self.sun, 0, bytemuck::cast_slice(&[light]));
         trace!("Updated light uniform");
     }
 
-    pub fn cache(&mut self, mesh: Mesh) -> CachedMesh {
-        CachedMesh::new(mesh, &mut self.device)
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.sky.sky = sky;
+    }
+
+    /// Set how far from the camera anything can be seen, in blocks. Terrain
+    /// fades into the sky on the way there, and chunks past it aren't drawn.
+    pub fn set_view_distance(&mut self, distance: f32) {
+        self.sky.view_distance = distance;
+    }
+
+    pub fn post_settings(&self) -> &PostSettings {
+        &self.post.settings
+    }
+
+    /// Set which effects are run over each frame, and how.
+    pub fn set_post_settings(&mut self, settings: PostSettings) {
+        self.post.settings = settings;
+    }
+
+    /// Turn a post effect on or off, returning whether it's now on.
+    pub fn toggle_post_effect(&mut self, effect: PostEffect) -> bool {
+        let effects = &mut self.post.settings.effects;
+        if !effects.remove(&effect) {
+            effects.insert(effect);
+        }
+        effects.contains(&effect)
+    }
+
+    /// Set the table [`PostEffect::ColorGrading`] looks colors up in, laid
+    /// out as described by [`PostChain::set_color_grading`], or go back to
+    /// one that leaves colors as they are.
+    pub fn set_color_grading(&mut self, lut: Option<&RgbaImage>) -> anyhow::Result<()> {
+        self.post.set_color_grading(&self.device, &self.queue, lut)
+    }
+
+    /// Set the materials that vertices index into.
+    pub fn palette(&mut self, materials: &[Material]) {
+        self.materials = materials.to_vec();
+
+        let entries: Vec<_> = materials
+            .iter()
+            .map(|material| PaletteEntry {
+                color: material.color,
+                layer: self.atlas.layer(material.texture.as_deref()),
+                emission: material.emission,
+                _padding: [0; 2],
+            })
+            .collect();
+
+        let size = std::mem::size_of_val(entries.as_slice()) as wgpu::BufferAddress;
+        if size <= self.palette.size() {
+            self.queue
+                .write_buffer(&self.palette, 0, bytemuck::cast_slice(&entries));
+        } else {
+            (self.palette, self.palette_bind_group) = create_palette(
+                &self.device,
+                &self.palette_bind_group_layout,
+                &entries,
+                &self.atlas,
+            );
+        }
+        trace!("Updated palette");
+    }
+
+    /// Load every PNG in `dir` into the texture atlas, replacing the
+    /// textures there before.
+    pub fn load_textures(&mut self, dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.atlas = Atlas::load(&self.device, &self.queue, dir.as_ref())?;
+
+        // the palette's bind group holds the old atlas, and its layers have
+        // moved
+        let materials = std::mem::take(&mut self.materials);
+        (self.palette, self.palette_bind_group) = create_palette(
+            &self.device,
+            &self.palette_bind_group_layout,
+            &[PaletteEntry::zeroed()],
+            &self.atlas,
+        );
+        self.palette(&materials);
+        Ok(())
+    }
+
+    pub fn cache(&mut self, mesh: ChunkMesh) -> CachedChunk {
+        CachedChunk::new(mesh, &mut self.arena, &self.device, &self.queue)
     }
 
-    pub fn update_cache(&mut self, cache: &mut CachedMesh, mesh: Mesh) {
-        cache.update(mesh, &mut self.device, &mut self.queue)
+    pub fn update_cache(&mut self, cache: &mut CachedChunk, mesh: ChunkMesh) {
+        cache.update(mesh, &mut self.arena, &self.device, &self.queue)
+    }
+
+    /// Release a chunk's space in the arena.
+    pub fn uncache(&mut self, cache: CachedChunk) {
+        cache.free(&mut self.arena);
+    }
+
+    /// The bytes used by cached chunks, and the total size of the buffers
+    /// holding them.
+    pub fn cache_usage(&self) -> (u64, u64) {
+        (self.arena.used(), self.arena.capacity())
+    }
+
+    /// Statistics about which chunks were culled in the last frame.
+    pub fn cull_stats(&self) -> CullStats {
+        self.cull_stats
+    }
+
+    /// The bytes used by a single cached chunk.
+    pub fn chunk_usage(&self, cache: &CachedChunk) -> u64 {
+        cache.memory(&self.arena)
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                Texture::create_depth_texture(&self.device, &self.config, "Depth texture");
+            match &mut self.target {
+                Target::Window { surface, config } => {
+                    config.width = new_size.width;
+                    config.height = new_size.height;
+                    surface.configure(&self.device, config);
+                }
+                Target::Offscreen { texture } => {
+                    *texture = create_offscreen(&self.device, new_size, self.format);
+                }
+            }
+            self.depth_texture = Texture::create_depth_texture(
+                &self.device,
+                [new_size.width, new_size.height],
+                "Depth texture",
+            );
+            self.post.resize(&self.device, new_size);
         }
     }
 
+    /// Draw a frame, presenting it if drawing to a window.
     pub fn render<'a>(
         &mut self,
-        meshes: impl IntoIterator<Item = &'a CachedMesh>,
+        chunks: impl IntoIterator<Item = &'a CachedChunk>,
     ) -> anyhow::Result<()> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let (output, view) = match &self.target {
+            Target::Window { surface, .. } => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&Default::default());
+                (Some(output), view)
+            }
+            Target::Offscreen { texture } => (None, texture.create_view(&Default::default())),
+        };
+
+        self.draw(chunks, &view);
+        if let Some(output) = output {
+            output.present();
+        }
+        Ok(())
+    }
+
+    /// Draw a frame and read it back, whether drawing to a window or not.
+    pub fn render_to_image<'a>(
+        &mut self,
+        chunks: impl IntoIterator<Item = &'a CachedChunk>,
+    ) -> anyhow::Result<RgbaImage> {
+        // frames presented to a window can't be read back, so draw this one
+        // into a texture like it instead
+        if let Target::Window { .. } = self.target {
+            let texture = create_offscreen(&self.device, self.size, self.format);
+            self.draw(chunks, &texture.create_view(&Default::default()));
+            return self.read_back(&texture);
+        }
+
+        self.render(chunks)?;
+        let Target::Offscreen { texture } = &self.target else {
+            unreachable!()
+        };
+        self.read_back(texture)
+    }
+
+    /// Draw a frame `scale` times the width and height of the current one,
+    /// as `scale` by `scale` tiles that each take up a slice of the camera's
+    /// view, and read it back.
+    ///
+    /// Effects that spread light across the frame, such as bloom, stop at
+    /// the edges of each tile.
+    pub fn render_poster<'a>(
+        &mut self,
+        chunks: impl IntoIterator<Item = &'a CachedChunk>,
+        scale: u32,
+    ) -> anyhow::Result<RgbaImage> {
+        anyhow::ensure!(scale > 0, "posters have to be at least the size of a frame");
+        let chunks: Vec<_> = chunks.into_iter().collect();
+        let PhysicalSize { width, height } = self.size;
+        let mut poster = RgbaImage::new(width * scale, height * scale);
+
+        let view_proj = self.view_proj;
+        let s = scale as f32;
+        for row in 0..scale {
+            for column in 0..scale {
+                // blow the tile's slice of clip space up to cover all of it;
+                // rows run down from the top
+                let offset = Vec3::new(
+                    s - 1.0 - 2.0 * column as f32,
+                    2.0 * row as f32 + 1.0 - s,
+                    0.0,
+                );
+                let tile =
+                    Mat4::<f32>::translation_3d(offset) * Mat4::scaling_3d(Vec3::new(s, s, 1.0));
+                self.set_view_proj(tile * view_proj);
+
+                let image = self.render_to_image(chunks.iter().copied());
+                let image = match image {
+                    Ok(image) => image,
+                    Err(err) => {
+                        self.set_view_proj(view_proj);
+                        return Err(err);
+                    }
+                };
+                let (x, y) = (column * width, row * height);
+                image::imageops::replace(&mut poster, &image, x.into(), y.into());
+            }
+        }
+
+        self.set_view_proj(view_proj);
+        Ok(poster)
+    }
+
+    /// Copy a frame back from the GPU.
+    fn read_back(&self, texture: &wgpu::Texture) -> anyhow::Result<RgbaImage> {
+        let bgra = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => anyhow::bail!("can't read back frames in {format:?}"),
+        };
+
+        let wgpu::Extent3d { width, height, .. } = texture.size();
+        // each row of the copy has to be aligned
+        let bytes_per_row = (4 * width).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut pixels: Vec<u8> = slice
+            .get_mapped_range()
+            .chunks(bytes_per_row as usize)
+            .flat_map(|row| &row[..4 * width as usize])
+            .copied()
+            .collect();
+        buffer.unmap();
+        if bgra {
+            pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+
+    fn draw<'a>(
+        &mut self,
+        chunks: impl IntoIterator<Item = &'a CachedChunk>,
+        view: &wgpu::TextureView,
+    ) {
+        self.arena.maintain(&self.device, &self.queue);
+
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
 
-        {
+        let mut chunks: Vec<_> = chunks.into_iter().collect();
+        let mut stats = CullStats {
+            chunks: chunks.len(),
+            ..Default::default()
+        };
+
+        let lod_distance = self.lod_distance;
+        let lod = |chunk: &CachedChunk| {
+            let distance = Vec3::from(chunk.center()).distance(self.eye);
+            let lod = if distance < lod_distance {
+                0
+            } else {
+                ((distance / lod_distance).log2() as usize + 1).min(LOD_LEVELS)
+            };
+            lod.min(chunk.lods() - 1)
+        };
+
+        // anything between the sun and the area around the camera can cast
+        // shadows into view, whether or not it's in view itself
+        let shadow_draws = match self.sun_direction {
+            Some(direction) => {
+                let view_proj = self.shadow.view_proj(self.eye, direction);
+                let frustum = Frustum::from_matrix(view_proj);
+                // the sun's view has no position; take one far enough away
+                // along its direction to see every face that faces it
+                let sun = self.eye + direction.normalized() * 1.0e4;
+
+                let mut draws = Vec::new();
+                for &chunk in chunks
+                    .iter()
+                    .filter(|chunk| frustum.intersects(chunk.bounds()))
+                {
+                    chunk.draws(Layer::Opaque, lod(chunk), &self.arena, sun, &mut draws);
+                    stats.shadow_casters += 1;
+                }
+
+                self.write_shadow(ShadowUniform {
+                    view_proj: view_proj.into_col_arrays(),
+                    enabled: 1,
+                    _padding: [0; 3],
+                });
+                Some(draws)
+            }
+            None => {
+                self.write_shadow(ShadowUniform {
+                    view_proj: Mat4::identity().into_col_arrays(),
+                    enabled: 0,
+                    _padding: [0; 3],
+                });
+                None
+            }
+        };
+
+        // the flood fill has to pass through every chunk, even those without
+        // anything to draw
+        let visible = self.occlusion_culling.then(|| {
+            let size = chunks.first()?.size;
+            let visibility = chunks
+                .iter()
+                .map(|chunk| (chunk.position(), chunk.visibility));
+            occlusion::visible_chunks(&visibility.collect(), size, self.eye, &self.frustum)
+        });
+
+        let view_distance = self.sky.view_distance;
+        chunks.retain(|chunk| chunk.bounds().distance_to_point(self.eye) < view_distance);
+        stats.distance_culled = stats.chunks - chunks.len();
+
+        chunks.retain(|chunk| self.frustum.intersects(chunk.bounds()));
+        stats.frustum_culled = stats.chunks - stats.distance_culled - chunks.len();
+
+        if let Some(Some(visible)) = visible {
+            chunks.retain(|chunk| visible.contains(&chunk.position()));
+        }
+        stats.occlusion_culled =
+            stats.chunks - stats.distance_culled - stats.frustum_culled - chunks.len();
+        stats.drawn = chunks.len();
+
+        // sort back-to-front; translucent faces have to be blended in that
+        // order, and everything else is drawn in reverse to make the most of
+        // the depth test
+        back_to_front(&mut chunks, self.eye, |chunk| chunk.center());
+
+        let chunks: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let lod = lod(chunk);
+                stats.lods[lod] += 1;
+                (chunk, lod)
+            })
+            .collect();
+
+        let draws = Layer::ALL.map(|layer| {
+            let mut draws = Vec::new();
+            let mut add = |&(chunk, lod): &(&CachedChunk, usize)| {
+                stats.direction_culled +=
+                    chunk.draws(layer, lod, &self.arena, self.eye, &mut draws);
+            };
+
+            if layer == Layer::Translucent {
+                chunks.iter().for_each(&mut add);
+            } else {
+                chunks.iter().rev().for_each(&mut add);
+            }
+            draws
+        });
+
+        self.cull_stats = stats;
+        trace!("{stats:?}");
+
+        if self.multi_draw {
+            let args: Vec<u8> = shadow_draws
+                .iter()
+                .chain(&draws)
+                .flatten()
+                .flat_map(|draw| draw.args.as_bytes())
+                .copied()
+                .collect();
+
+            if args.len() as u64 > self.indirect.size() {
+                self.indirect = create_indirect(&self.device, args.len() as u64);
+            }
+            if !args.is_empty() {
+                self.queue.write_buffer(&self.indirect, 0, &args);
+            }
+        }
+
+        let sky = self.sky.uniform(self.eye, self.view_proj);
+        self.queue
+            .write_buffer(&self.sky.buffer, 0, bytemuck::cast_slice(&[sky]));
+
+        let mut indirect_offset = 0;
+
+        if let Some(draws) = &shadow_draws {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.shadow.texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            shadow_pass.set_pipeline(&self.shadow.pipeline);
+            shadow_pass.set_bind_group(0, &self.shadow.camera_bind_group, &[]);
+            shadow_pass.set_bind_group(1, &self.shadow.empty_bind_group, &[]);
+            shadow_pass.set_bind_group(2, &self.palette_bind_group, &[]);
+
+            let indirect = self.multi_draw.then_some((&self.indirect, indirect_offset));
+            self.arena.draw(&mut shadow_pass, draws, indirect);
+            indirect_offset += indirect_size(draws);
+            trace!("Made shadow pass");
+        }
+
+        let clear_color = if self.mode.shaded() {
+            self.sky.clear_color()
+        } else {
+            wgpu::Color::BLACK
+        };
+        for layer in Layer::ALL {
+            let (color_load, depth_load) = match layer {
+                Layer::Opaque => (wgpu::LoadOp::Clear(clear_color), wgpu::LoadOp::Clear(1.0)),
+                _ => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+            };
+
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: self.post.frame(),
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        load: color_load,
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: depth_load,
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -377,27 +1128,55 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.pipelines[0]);
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
             render_pass.set_bind_group(1, &self.sun_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.palette_bind_group, &[]);
 
-            for mesh in meshes {
-                render_pass.set_vertex_buffer(0, mesh.vertices.slice(..));
-                render_pass.set_index_buffer(mesh.indices.slice(..), wgpu::IndexFormat::Uint32);
+            let draws = &draws[layer as usize];
+            let indirect = self.multi_draw.then_some((&self.indirect, indirect_offset));
+            for pipeline in &self.pipelines[layer as usize] {
+                render_pass.set_pipeline(pipeline);
+                self.arena.draw(&mut render_pass, draws, indirect);
+            }
+            indirect_offset += indirect_size(draws);
 
-                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
-                trace!("Making render pass");
+            // fill in the sky behind the opaque terrain, before anything is
+            // blended over it
+            if layer == Layer::Opaque && self.mode.shaded() {
+                render_pass.set_pipeline(&self.sky.pipeline);
+                render_pass.draw(0..3, 0..1);
             }
+            trace!("Made {layer:?} render pass");
         }
 
+        let srgb_target = self.format.is_srgb();
+        self.post.run(
+            &mut encoder,
+            &self.queue,
+            view,
+            srgb_target,
+            self.mode.shaded(),
+        );
+        trace!("Made post passes");
+
         self.queue.submit([encoder.finish()]);
-        output.present();
         trace!("Queue flushed");
-        Ok(())
+    }
+
+    fn write_shadow(&self, shadow: ShadowUniform) {
+        self.queue
+            .write_buffer(&self.shadow.buffer, 0, bytemuck::cast_slice(&[shadow]));
     }
 
     pub fn update_camera(&mut self, camera: &Camera) {
-        self.camera_uniform.update_view_proj(camera);
+        self.eye = camera.eye;
+        self.set_view_proj(camera.build_view_projection_matrix());
+    }
+
+    fn set_view_proj(&mut self, view_proj: Mat4<f32>) {
+        self.view_proj = view_proj;
+        self.frustum = Frustum::from_matrix(view_proj);
+        self.camera_uniform.set_view_proj(view_proj);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
@@ -405,3 +1184,203 @@ impl Renderer {
         );
     }
 }
+
+/// The features needed to draw every chunk layer at once.
+const MULTI_DRAW_FEATURES: wgpu::Features =
+    wgpu::Features::MULTI_DRAW_INDIRECT.union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
+/// Ask for a device with every feature the renderer can make use of that the
+/// adapter supports.
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let required_features =
+        adapter.features() & (MULTI_DRAW_FEATURES | wgpu::Features::POLYGON_MODE_LINE);
+    let device = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                required_features,
+                required_limits: adapter.limits(),
+            },
+            None,
+        )
+        .await?;
+    Ok(device)
+}
+
+/// Create a texture to draw frames into and read them back from.
+fn create_offscreen(
+    device: &wgpu::Device,
+    size: PhysicalSize<u32>,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen"),
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Build the pipelines every layer is drawn with in `mode`.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    modules: &Modules,
+    mode: RenderMode,
+) -> [Vec<wgpu::RenderPipeline>; 2] {
+    Layer::ALL.map(|layer| {
+        mode.pipelines(layer)
+            .iter()
+            .map(|config| create_pipeline(device, layout, modules, layer, config))
+            .collect()
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    modules: &Modules,
+    layer: Layer,
+    config: &PipelineConfig,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{layer:?} {} render", config.entry_point)),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &modules.vert,
+            entry_point: "main",
+            buffers: &[Vertex::desc(), Instance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &modules.frag,
+            entry_point: config.entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: config.blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: config.polygon_mode,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: config.depth_write,
+            depth_compare: config.depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+fn create_palette(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    entries: &[PaletteEntry],
+    atlas: &Atlas,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let palette = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Palette Buffer"),
+        contents: bytemuck::cast_slice(entries),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: palette.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&atlas.texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&atlas.texture.sampler),
+            },
+        ],
+        label: Some("palette_bind_group"),
+    });
+
+    (palette, bind_group)
+}
+
+/// The number of bytes the indirect arguments of `draws` take up.
+fn indirect_size(draws: &[cached::Draw]) -> u64 {
+    (draws.len() * std::mem::size_of::<DrawIndexedIndirectArgs>()) as u64
+}
+
+/// Create a buffer for at least `size` bytes of indirect draw arguments.
+/// Sort things from the furthest from `eye` to the nearest, by their centers.
+fn back_to_front<T>(items: &mut [T], eye: Vec3<f32>, center: impl Fn(&T) -> [f32; 3]) {
+    let distance = |item: &T| Vec3::from(center(item)).distance_squared(eye);
+    items.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+}
+
+fn create_indirect(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Indirect Buffer"),
+        size: size.next_power_of_two().max(4096),
+        usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_back_to_front() {
+        let mut centers = [
+            [16.0, 16.0, 16.0],
+            [-48.0, 16.0, 16.0],
+            [16.0, 16.0, 80.0],
+            [16.0, -16.0, 16.0],
+        ];
+        back_to_front(&mut centers, Vec3::new(0.0, 0.0, 0.0), |&center| center);
+        assert_eq!(
+            centers,
+            [
+                [16.0, 16.0, 80.0],
+                [-48.0, 16.0, 16.0],
+                [16.0, 16.0, 16.0],
+                [16.0, -16.0, 16.0],
+            ]
+        );
+
+        // from the other side, what was nearest is now furthest
+        back_to_front(&mut centers, Vec3::new(200.0, 16.0, 16.0), |&center| center);
+        assert_eq!(
+            centers,
+            [
+                [-48.0, 16.0, 16.0],
+                [16.0, 16.0, 80.0],
+                [16.0, -16.0, 16.0],
+                [16.0, 16.0, 16.0],
+            ]
+        );
+    }
+}
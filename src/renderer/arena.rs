@@ -0,0 +1,550 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use log::debug;
+
+/// The fraction of free space that has to be unusable before the arena is
+/// compacted instead of grown.
+const FRAGMENTATION_LIMIT: f64 = 0.5;
+/// How many freed blocks of each size class are kept for reuse, before they're
+/// returned to the free list.
+const POOL_LIMIT: usize = 16;
+/// How many updates in a row an allocation has to use less than half of its
+/// block for before it's moved to a smaller one.
+const SHRINK_AFTER_UPDATES: u32 = 4;
+/// How many frames in a row the arena has to be less than a quarter full for
+/// before its buffer is shrunk.
+const SHRINK_AFTER_FRAMES: u32 = 600;
+
+/// A handle to a block of memory in a [`BufferArena`].
+///
+/// The block may move when the arena grows, shrinks or is compacted, so its
+/// range has to be looked up with [`BufferArena::range`] each time it's used.
+#[derive(Debug)]
+pub struct Allocation {
+    id: u32,
+}
+
+#[derive(Debug)]
+struct Block {
+    range: Range<u64>,
+    /// How many updates in a row have used less than half of the block.
+    low_use: u32,
+}
+
+/// A single GPU buffer, sub-allocated between many users.
+///
+/// Allocations are rounded up to a size class, so that freed blocks can be
+/// pooled and handed straight to the next allocation of the same class. Other
+/// free space is tracked as a list of blocks, coalesced as they're freed. When
+/// an allocation doesn't fit anywhere, the arena is either compacted (if
+/// enough space is free, but too fragmented to use) or grown, copying every
+/// live block into a new buffer.
+pub struct BufferArena {
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    buffer: wgpu::Buffer,
+    allocator: Allocator,
+}
+
+/// Where everything in a [`BufferArena`] is, apart from the buffer itself.
+#[derive(Debug)]
+struct Allocator {
+    align: u64,
+    min_capacity: u64,
+    capacity: u64,
+    /// Free blocks, by offset.
+    free: BTreeMap<u64, u64>,
+    /// Freed blocks ready for reuse, by size class.
+    pool: HashMap<u64, Vec<u64>>,
+    /// Each allocation's block, indexed by id.
+    blocks: Vec<Option<Block>>,
+    unused_ids: Vec<u32>,
+    used: u64,
+    /// How many frames in a row the arena has been mostly empty.
+    low_use: u32,
+}
+
+impl BufferArena {
+    /// Create an arena. Every allocation is a multiple of `align` bytes,
+    /// which itself must be a multiple of [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        align: u64,
+        capacity: u64,
+    ) -> Self {
+        debug_assert!(align.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT));
+
+        let allocator = Allocator::new(align, capacity);
+        let usage = usage | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        Self {
+            label,
+            usage,
+            buffer: create_buffer(device, label, usage, allocator.capacity),
+            allocator,
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// The current byte range of an allocation.
+    pub fn range(&self, allocation: &Allocation) -> Range<u64> {
+        self.allocator.range(allocation)
+    }
+
+    /// The number of bytes reserved for an allocation, which may be more than
+    /// its contents.
+    pub fn size(&self, allocation: &Allocation) -> u64 {
+        self.allocator.size(allocation)
+    }
+
+    /// The total size of the arena's buffer in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.allocator.capacity
+    }
+
+    /// The number of bytes currently allocated, including the unused ends of
+    /// blocks.
+    pub fn used(&self) -> u64 {
+        self.allocator.used
+    }
+
+    /// The fraction of free space outside of the largest free block, i.e.
+    /// that can't be used for a large allocation.
+    pub fn fragmentation(&self) -> f64 {
+        self.allocator.fragmentation()
+    }
+
+    /// Allocate a block and fill it with `contents`, growing or compacting
+    /// the arena if necessary.
+    pub fn alloc(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        contents: &[u8],
+    ) -> Allocation {
+        let len = contents.len() as u64;
+        let allocation = self.allocator.alloc(len).unwrap_or_else(|capacity| {
+            self.relocate(device, queue, capacity);
+            self.allocator
+                .alloc(len)
+                .expect("relocated arena is too small")
+        });
+
+        self.write(queue, &allocation, contents);
+        allocation
+    }
+
+    /// Replace the contents of an allocation.
+    ///
+    /// The allocation is moved if the contents don't fit, or if they've been
+    /// much smaller than it for a while.
+    pub fn realloc(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        allocation: &mut Allocation,
+        contents: &[u8],
+    ) {
+        if self.allocator.fits(allocation, contents.len() as u64) {
+            self.write(queue, allocation, contents);
+        } else {
+            let old = std::mem::replace(allocation, self.alloc(device, queue, contents));
+            self.free(old);
+        }
+    }
+
+    /// Free an allocation, keeping its block for reuse or merging it with any
+    /// adjacent free blocks.
+    pub fn free(&mut self, allocation: Allocation) {
+        self.allocator.free(allocation);
+    }
+
+    /// Shrink the arena's buffer if it's been mostly empty for a while. Should
+    /// be called once per frame.
+    pub fn maintain(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(capacity) = self.allocator.maintain() {
+            self.relocate(device, queue, capacity);
+        }
+    }
+
+    fn write(&self, queue: &wgpu::Queue, allocation: &Allocation, contents: &[u8]) {
+        if contents.is_empty() {
+            return;
+        }
+
+        let start = self.range(allocation).start;
+        let len = (contents.len() as u64).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        if len == contents.len() as u64 {
+            queue.write_buffer(&self.buffer, start, contents);
+        } else {
+            let mut padded = contents.to_vec();
+            padded.resize(len as usize, 0);
+            queue.write_buffer(&self.buffer, start, &padded);
+        }
+    }
+
+    /// Pack every allocation into a new buffer of the given capacity.
+    fn relocate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, capacity: u64) {
+        let buffer = create_buffer(device, self.label, self.usage, capacity);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Arena relocation"),
+        });
+
+        let old_capacity = self.capacity();
+        for (from, to, size) in self.allocator.relocate(capacity) {
+            encoder.copy_buffer_to_buffer(&self.buffer, from, &buffer, to, size);
+        }
+
+        // pending writes to the old buffer are flushed before the copy
+        queue.submit([encoder.finish()]);
+
+        debug!(
+            "Relocated {} arena: {old_capacity} -> {capacity} bytes, {} used",
+            self.label,
+            self.used(),
+        );
+        self.buffer = buffer;
+    }
+}
+
+impl Allocator {
+    fn new(align: u64, capacity: u64) -> Self {
+        let capacity = capacity.next_multiple_of(align);
+        Self {
+            align,
+            min_capacity: capacity,
+            capacity,
+            free: BTreeMap::from([(0, capacity)]),
+            pool: HashMap::new(),
+            blocks: Vec::new(),
+            unused_ids: Vec::new(),
+            used: 0,
+            low_use: 0,
+        }
+    }
+
+    fn block(&self, allocation: &Allocation) -> &Block {
+        self.blocks[allocation.id as usize].as_ref().unwrap()
+    }
+
+    fn range(&self, allocation: &Allocation) -> Range<u64> {
+        self.block(allocation).range.clone()
+    }
+
+    fn size(&self, allocation: &Allocation) -> u64 {
+        let range = self.range(allocation);
+        range.end - range.start
+    }
+
+    fn fragmentation(&self) -> f64 {
+        let free = self.capacity - self.used;
+        if free == 0 {
+            return 0.0;
+        }
+
+        let largest = self.free.values().copied().max().unwrap_or(0);
+        1.0 - largest as f64 / free as f64
+    }
+
+    /// Reserve a block for `len` bytes, or if there's no room, the capacity
+    /// to relocate the arena to first.
+    fn alloc(&mut self, len: u64) -> Result<Allocation, u64> {
+        let size = self.size_class(len);
+
+        let Some(offset) = self.find(size) else {
+            let free = self.capacity - self.used;
+            let capacity = if free >= size && self.fragmentation() > FRAGMENTATION_LIMIT {
+                self.capacity
+            } else {
+                (self.used + size)
+                    .next_power_of_two()
+                    .max(self.capacity * 2)
+                    .next_multiple_of(self.align)
+            };
+            return Err(capacity);
+        };
+
+        let block = Block {
+            range: offset..offset + size,
+            low_use: 0,
+        };
+        self.used += size;
+
+        let id = match self.unused_ids.pop() {
+            Some(id) => {
+                self.blocks[id as usize] = Some(block);
+                id
+            }
+            None => {
+                self.blocks.push(Some(block));
+                self.blocks.len() as u32 - 1
+            }
+        };
+        Ok(Allocation { id })
+    }
+
+    /// Whether `len` bytes can replace an allocation's contents in place,
+    /// counting another update towards shrinking it if they're much smaller.
+    fn fits(&mut self, allocation: &Allocation, len: u64) -> bool {
+        let needed = self.size_class(len);
+        let size = self.size(allocation);
+
+        let block = self.blocks[allocation.id as usize].as_mut().unwrap();
+        let shrink = if needed * 2 <= size {
+            block.low_use += 1;
+            block.low_use >= SHRINK_AFTER_UPDATES
+        } else {
+            block.low_use = 0;
+            false
+        };
+
+        needed <= size && !shrink
+    }
+
+    fn free(&mut self, allocation: Allocation) {
+        let Block { range, .. } = self.blocks[allocation.id as usize].take().unwrap();
+        self.unused_ids.push(allocation.id);
+
+        let size = range.end - range.start;
+        self.used -= size;
+        if size == 0 {
+            return;
+        }
+
+        let pool = self.pool.entry(size).or_default();
+        if pool.len() < POOL_LIMIT {
+            pool.push(range.start);
+        } else {
+            self.release(range);
+        }
+    }
+
+    /// The capacity to shrink the arena to, once it's been mostly empty for
+    /// long enough.
+    fn maintain(&mut self) -> Option<u64> {
+        if self.capacity <= self.min_capacity || self.used * 4 >= self.capacity {
+            self.low_use = 0;
+            return None;
+        }
+
+        self.low_use += 1;
+        if self.low_use < SHRINK_AFTER_FRAMES {
+            return None;
+        }
+
+        self.low_use = 0;
+        let capacity = (self.used * 2)
+            .next_power_of_two()
+            .max(self.min_capacity)
+            .next_multiple_of(self.align);
+        Some(capacity)
+    }
+
+    /// Round a size up to its size class: four classes per power of two, so
+    /// at most a fifth of each block is wasted.
+    fn size_class(&self, size: u64) -> u64 {
+        if size == 0 {
+            return 0;
+        }
+
+        let step = (size.next_power_of_two() / 8).max(1);
+        size.next_multiple_of(step).next_multiple_of(self.align)
+    }
+
+    /// Return a block to the free list, merging it with its neighbours.
+    fn release(&mut self, Range { mut start, mut end }: Range<u64>) {
+        if let Some((&before, &size)) = self.free.range(..start).next_back() {
+            if before + size == start {
+                self.free.remove(&before);
+                start = before;
+            }
+        }
+
+        if let Some(size) = self.free.remove(&end) {
+            end += size;
+        }
+
+        self.free.insert(start, end - start);
+    }
+
+    /// Find a free block for `size` bytes, preferring pooled blocks of the
+    /// same size class.
+    fn find(&mut self, size: u64) -> Option<u64> {
+        if size == 0 {
+            // empty allocations don't take up any space
+            return Some(0);
+        }
+
+        if let Some(offset) = self.pool.get_mut(&size).and_then(Vec::pop) {
+            return Some(offset);
+        }
+
+        let (&offset, &free) = self.free.iter().find(|(_, &free)| free >= size)?;
+
+        self.free.remove(&offset);
+        if free > size {
+            self.free.insert(offset + size, free - size);
+        }
+
+        Some(offset)
+    }
+
+    /// Pack every allocation to the front of a buffer of the given capacity,
+    /// returning where each non-empty block moved from and to, and its size.
+    fn relocate(&mut self, capacity: u64) -> Vec<(u64, u64, u64)> {
+        debug_assert!(capacity >= self.used);
+
+        // keep allocations in the same order, so neighbours stay neighbours
+        let mut live: Vec<_> = self
+            .blocks
+            .iter_mut()
+            .flatten()
+            .map(|block| &mut block.range)
+            .collect();
+        live.sort_by_key(|range| range.start);
+
+        let mut moves = Vec::new();
+        let mut offset = 0;
+        for range in live {
+            let size = range.end - range.start;
+            if size > 0 {
+                moves.push((range.start, offset, size));
+            }
+
+            *range = offset..offset + size;
+            offset += size;
+        }
+
+        self.capacity = capacity;
+        self.pool.clear();
+        self.free.clear();
+        if offset < capacity {
+            self.free.insert(offset, capacity - offset);
+        }
+        moves
+    }
+}
+
+fn create_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    usage: wgpu::BufferUsages,
+    size: u64,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_to_size_classes() {
+        let allocator = Allocator::new(4, 1024);
+        assert_eq!(allocator.size_class(0), 0);
+        assert_eq!(allocator.size_class(1), 4);
+        assert_eq!(allocator.size_class(100), 112);
+        assert_eq!(allocator.size_class(129), 160);
+        assert_eq!(allocator.size_class(1000), 1024);
+        assert_eq!(allocator.size_class(1024), 1024);
+    }
+
+    #[test]
+    fn reuses_pooled_blocks() {
+        let mut allocator = Allocator::new(4, 1024);
+        let a = allocator.alloc(100).unwrap();
+        let b = allocator.alloc(100).unwrap();
+        assert_eq!(allocator.range(&b), 112..224);
+
+        allocator.free(a);
+        assert_eq!(allocator.used, 112);
+        let c = allocator.alloc(97).unwrap();
+        assert_eq!(allocator.range(&c), 0..112);
+    }
+
+    #[test]
+    fn coalesces_released_blocks() {
+        let mut allocator = Allocator::new(4, 4096);
+        let allocations: Vec<_> = (0..20).map(|_| allocator.alloc(64).unwrap()).collect();
+        for allocation in allocations {
+            allocator.free(allocation);
+        }
+        // the pool is full after 16, and the rest merge with the space after
+        assert_eq!(allocator.pool[&64].len(), POOL_LIMIT);
+        assert_eq!(allocator.free, BTreeMap::from([(1024, 3072)]));
+
+        let mut allocator = Allocator::new(4, 192);
+        allocator.free.clear();
+        allocator.release(0..64);
+        allocator.release(128..192);
+        assert_eq!(allocator.free.len(), 2);
+        allocator.release(64..128);
+        assert_eq!(allocator.free, BTreeMap::from([(0, 192)]));
+    }
+
+    #[test]
+    fn compacts_or_grows_when_full() {
+        let mut allocator = Allocator::new(4, 1024);
+        let allocations: Vec<_> = (0..8).map(|_| allocator.alloc(128).unwrap()).collect();
+        assert_eq!(allocator.alloc(8).unwrap_err(), 2048);
+
+        // enough space is free, but in pieces too small to use
+        let mut allocations = allocations.into_iter();
+        for _ in 0..3 {
+            allocator.free(allocations.next().unwrap());
+            allocations.next();
+        }
+        assert_eq!(allocator.alloc(384).unwrap_err(), 1024);
+
+        let moves = allocator.relocate(1024);
+        assert_eq!(moves.len(), 5);
+        assert_eq!(moves[0], (128, 0, 128));
+        assert_eq!(
+            allocator.alloc(384).map(|c| allocator.range(&c)),
+            Ok(640..1024)
+        );
+    }
+
+    #[test]
+    fn shrinks_underused_blocks() {
+        let mut allocator = Allocator::new(4, 1024);
+        let a = allocator.alloc(1000).unwrap();
+        for _ in 1..SHRINK_AFTER_UPDATES {
+            assert!(allocator.fits(&a, 100));
+        }
+        assert!(!allocator.fits(&a, 100));
+
+        // using most of the block starts the count again
+        assert!(allocator.fits(&a, 600));
+        assert!(allocator.fits(&a, 100));
+        assert!(!allocator.fits(&a, 2000));
+    }
+
+    #[test]
+    fn shrinks_underused_arena() {
+        let mut allocator = Allocator::new(4, 1024);
+        let a = allocator.alloc(1000).unwrap();
+        let capacity = allocator.alloc(1000).unwrap_err();
+        allocator.relocate(capacity);
+        let b = allocator.alloc(1000).unwrap();
+        assert_eq!(allocator.capacity, 2048);
+        assert_eq!(allocator.maintain(), None);
+
+        allocator.free(a);
+        allocator.free(b);
+        for _ in 1..SHRINK_AFTER_FRAMES {
+            assert_eq!(allocator.maintain(), None);
+        }
+        assert_eq!(allocator.maintain(), Some(1024));
+    }
+}
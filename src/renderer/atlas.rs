@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::{imageops, Rgba, RgbaImage};
+use log::{debug, warn};
+
+use super::texture::Texture;
+
+/// Block textures, each in its own layer of a texture array, so that they
+/// can repeat across faces of any size.
+///
+/// The first layer is plain white, for faces without a texture of their own
+/// to show their color as it is.
+pub struct Atlas {
+    pub(super) texture: Texture,
+    /// The layer of each texture, by name.
+    names: HashMap<String, u32>,
+}
+
+impl Atlas {
+    /// Load every PNG in `dir` into an atlas, each named after its file
+    /// without the extension.
+    ///
+    /// Every texture is scaled to the size of the first, in alphabetical
+    /// order.
+    pub fn load(device: &wgpu::Device, queue: &wgpu::Queue, dir: &Path) -> Result<Self> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("reading {dir:?}"))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "png") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut textures = Vec::new();
+        for path in paths {
+            let image = image::open(&path).with_context(|| format!("loading {path:?}"))?;
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            textures.push((name, image.to_rgba8()));
+        }
+
+        debug!("Loaded {} textures from {dir:?}", textures.len());
+        Ok(Self::new(device, queue, textures))
+    }
+
+    /// Put each of `textures` in its own layer, by name, with mipmaps made
+    /// from that layer alone.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        textures: Vec<(String, RgbaImage)>,
+    ) -> Self {
+        let Layers {
+            size,
+            count,
+            mips,
+            names,
+        } = Layers::new(textures);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture atlas"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: count,
+            },
+            mip_level_count: mips[0].len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, mips) in mips.iter().enumerate() {
+            for (mip_level, image) in mips.iter().enumerate() {
+                let mip_size = image.width();
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &texture,
+                        mip_level: mip_level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    image,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * mip_size),
+                        rows_per_image: Some(mip_size),
+                    },
+                    wgpu::Extent3d {
+                        width: mip_size,
+                        height: mip_size,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // keep the texels crisp up close, and blend them together in the
+        // distance
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Atlas sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture: Texture {
+                texture,
+                view,
+                sampler,
+            },
+            names,
+        }
+    }
+
+    /// An atlas with only the white layer.
+    pub fn empty(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::new(device, queue, Vec::new())
+    }
+
+    /// The layer of a texture, or the white layer if there's no such
+    /// texture.
+    pub fn layer(&self, name: Option<&str>) -> u32 {
+        let layer = name.map_or(Some(0), |name| self.names.get(name).copied());
+        layer.unwrap_or_else(|| {
+            warn!("No texture named {:?}", name.unwrap());
+            0
+        })
+    }
+}
+
+/// The contents of an [`Atlas`]'s texture.
+struct Layers {
+    /// The width and height of every layer.
+    size: u32,
+    /// The number of layers in the texture, which may be more than are used.
+    count: u32,
+    /// Each used layer's mip levels, from full size down to a single texel.
+    mips: Vec<Vec<RgbaImage>>,
+    /// The layer of each texture, by name.
+    names: HashMap<String, u32>,
+}
+
+impl Layers {
+    /// Lay out the white layer followed by `textures`, all scaled to the
+    /// size of the first.
+    fn new(textures: Vec<(String, RgbaImage)>) -> Self {
+        let size = textures.first().map_or(1, |(_, image)| image.width());
+        let mip_level_count = size.ilog2() + 1;
+        // the GL backend takes a texture with a single layer to be a plain 2D
+        // texture, and a square one with a multiple of six layers to be a
+        // cube map, neither of which can be bound as an array
+        let mut count = (textures.len() as u32 + 1).max(2);
+        if count.is_multiple_of(6) {
+            count += 1;
+        }
+
+        let mut names = HashMap::new();
+        let mut mips = Vec::new();
+        let white = RgbaImage::from_pixel(size, size, Rgba([255; 4]));
+        let textures = std::iter::once((String::new(), white)).chain(textures);
+        for (layer, (name, mut image)) in textures.enumerate() {
+            if image.dimensions() != (size, size) {
+                warn!(
+                    "Texture {name:?} is {:?}, not {size}x{size}; scaling it to fit",
+                    image.dimensions()
+                );
+                image = imageops::resize(&image, size, size, imageops::FilterType::Nearest);
+            }
+
+            let mut levels = vec![image];
+            for mip_level in 1..mip_level_count {
+                let mip_size = (size >> mip_level).max(1);
+                let smaller = imageops::resize(
+                    levels.last().unwrap(),
+                    mip_size,
+                    mip_size,
+                    imageops::FilterType::Triangle,
+                );
+                levels.push(smaller);
+            }
+            mips.push(levels);
+
+            if layer > 0 {
+                names.insert(name, layer as u32);
+            }
+        }
+
+        Self {
+            size,
+            count,
+            mips,
+            names,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture(name: &str, size: u32, color: u8) -> (String, RgbaImage) {
+        let image = RgbaImage::from_pixel(size, size, Rgba([color, color, color, 255]));
+        (name.to_owned(), image)
+    }
+
+    #[test]
+    fn scales_textures_to_the_first() {
+        let layers = Layers::new(vec![
+            texture("grass", 16, 10),
+            texture("stone", 8, 20),
+            texture("dirt", 32, 30),
+        ]);
+
+        assert_eq!(layers.size, 16);
+        assert_eq!(
+            layers.names,
+            HashMap::from([
+                ("grass".to_owned(), 1),
+                ("stone".to_owned(), 2),
+                ("dirt".to_owned(), 3),
+            ])
+        );
+
+        // the white layer comes first
+        let colors: Vec<_> = layers
+            .mips
+            .iter()
+            .map(|mips| mips[0].get_pixel(0, 0)[0])
+            .collect();
+        assert_eq!(colors, [255, 10, 20, 30]);
+        for mips in &layers.mips {
+            assert_eq!(mips[0].dimensions(), (16, 16));
+        }
+    }
+
+    #[test]
+    fn mips_down_to_a_single_texel() {
+        let layers = Layers::new(vec![texture("grass", 16, 10), texture("stone", 64, 20)]);
+        for mips in &layers.mips {
+            let sizes: Vec<_> = mips.iter().map(|image| image.dimensions()).collect();
+            assert_eq!(sizes, [(16, 16), (8, 8), (4, 4), (2, 2), (1, 1)]);
+            // a flat color stays the same all the way down
+            assert_eq!(mips[4].get_pixel(0, 0), mips[0].get_pixel(0, 0));
+        }
+
+        let empty = Layers::new(Vec::new());
+        assert_eq!(empty.size, 1);
+        assert_eq!(empty.mips.len(), 1);
+        assert_eq!(empty.mips[0].len(), 1);
+    }
+
+    #[test]
+    fn avoids_layer_counts_gl_cannot_bind_as_arrays() {
+        let count = |textures: usize| {
+            let textures = (0..textures)
+                .map(|i| texture(&i.to_string(), 2, 0))
+                .collect();
+            Layers::new(textures).count
+        };
+        assert_eq!(count(0), 2);
+        assert_eq!(count(1), 2);
+        assert_eq!(count(4), 5);
+        assert_eq!(count(5), 7);
+        assert_eq!(count(11), 13);
+    }
+}
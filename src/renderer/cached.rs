@@ -1,61 +1,541 @@
-use wgpu::util::DeviceExt;
+use std::ops::Range;
 
-use super::{vertex, Mesh};
+use vek::{Aabb, Vec3};
+use wgpu::util::DrawIndexedIndirectArgs;
+
+use super::arena::{Allocation, BufferArena};
+use super::mesh::{ChunkMesh, Layer, Mesh, MeshStats, FACE_GROUPS};
+use super::occlusion::Visibility;
+use super::vertex::{self, Instance};
+use crate::app::voxel::Direction;
+
+const INSTANCE_SIZE: u64 = std::mem::size_of::<Instance>() as u64;
+
+/// The buffers shared by every [`CachedChunk`].
+pub struct ChunkArena {
+    vertices: BufferArena,
+    /// Indices of each format, indexed by [`index_slot`].
+    indices: [BufferArena; 2],
+    instances: BufferArena,
+}
+
+impl ChunkArena {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let index_arena = |label, size| {
+            BufferArena::new(
+                device,
+                label,
+                wgpu::BufferUsages::INDEX,
+                wgpu::COPY_BUFFER_ALIGNMENT,
+                size,
+            )
+        };
+
+        Self {
+            vertices: BufferArena::new(
+                device,
+                "Vertex Arena",
+                wgpu::BufferUsages::VERTEX,
+                vertex::VERTEX_SIZE as u64,
+                4 << 20,
+            ),
+            indices: [
+                index_arena("Index Arena (u16)", 2 << 20),
+                // only chunks with more than 65536 vertices in a layer end up here
+                index_arena("Index Arena (u32)", 64 << 10),
+            ],
+            instances: BufferArena::new(
+                device,
+                "Instance Arena",
+                wgpu::BufferUsages::VERTEX,
+                INSTANCE_SIZE,
+                INSTANCE_SIZE * 256,
+            ),
+        }
+    }
+
+    /// Draw a list of meshes, with one indirect draw for each run of meshes
+    /// sharing an index format. Without `indirect`, each mesh is drawn
+    /// separately instead.
+    ///
+    /// `indirect` holds the arguments of every draw in `draws`, from the given
+    /// offset.
+    pub(super) fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        draws: &[Draw],
+        indirect: Option<(&'a wgpu::Buffer, wgpu::BufferAddress)>,
+    ) {
+        render_pass.set_vertex_buffer(0, self.vertices.buffer().slice(..));
+        if indirect.is_some() {
+            render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
+        }
+
+        let mut start = 0;
+        for run in draws.chunk_by(|a, b| a.format == b.format) {
+            let format = run[0].format;
+            let indices = self.indices[index_slot(format)].buffer();
+            render_pass.set_index_buffer(indices.slice(..), format);
+
+            match indirect {
+                Some((buffer, offset)) => {
+                    let offset =
+                        offset + (start * std::mem::size_of::<DrawIndexedIndirectArgs>()) as u64;
+                    render_pass.multi_draw_indexed_indirect(buffer, offset, run.len() as u32);
+                }
+                None => {
+                    for draw in run {
+                        let args = draw.args;
+                        let instance = args.first_instance as u64 * INSTANCE_SIZE;
+                        render_pass.set_vertex_buffer(
+                            1,
+                            self.instances
+                                .buffer()
+                                .slice(instance..instance + INSTANCE_SIZE),
+                        );
+                        render_pass.draw_indexed(
+                            args.first_index..args.first_index + args.index_count,
+                            args.base_vertex,
+                            0..1,
+                        );
+                    }
+                }
+            }
+
+            start += run.len();
+        }
+    }
+
+    /// Shrink any of the arena's buffers that have been mostly empty for a
+    /// while.
+    pub fn maintain(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.vertices.maintain(device, queue);
+        self.instances.maintain(device, queue);
+        for indices in &mut self.indices {
+            indices.maintain(device, queue);
+        }
+    }
+
+    /// The total size of the arena's buffers in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.arenas().map(BufferArena::capacity).sum()
+    }
+
+    /// The number of bytes used by cached meshes.
+    pub fn used(&self) -> u64 {
+        self.arenas().map(BufferArena::used).sum()
+    }
+
+    fn arenas(&self) -> impl Iterator<Item = &BufferArena> {
+        [&self.vertices, &self.instances]
+            .into_iter()
+            .chain(&self.indices)
+    }
+}
+
+/// A single draw of a chunk layer out of the [`ChunkArena`].
+#[derive(Debug, Clone, Copy)]
+pub struct Draw {
+    pub format: wgpu::IndexFormat,
+    pub args: DrawIndexedIndirectArgs,
+}
+
+/// A chunk's mesh, stored in the [`ChunkArena`] with a separate set of
+/// allocations per [`Layer`].
+pub struct CachedChunk {
+    pub(super) origin: [f32; 3],
+    pub(super) size: f32,
+    /// The layers of each level of detail, from full resolution down.
+    pub(super) lods: Vec<[CachedMesh; 2]>,
+    pub(super) bounds: Aabb<f32>,
+    pub(super) visibility: Visibility,
+    /// The [`Instance`] placing the chunk in the world.
+    pub(super) instance: Allocation,
+    stats: MeshStats,
+}
+
+impl CachedChunk {
+    pub fn new(
+        mesh: ChunkMesh,
+        arena: &mut ChunkArena,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let instance =
+            arena
+                .instances
+                .alloc(device, queue, bytemuck::cast_slice(&[Instance::new(&mesh)]));
+
+        Self {
+            origin: mesh.origin,
+            size: mesh.size,
+            bounds: mesh.bounds,
+            visibility: mesh.visibility,
+            stats: mesh.stats,
+            instance,
+            lods: std::iter::once(mesh.layers)
+                .chain(mesh.lods)
+                .map(|layers| layers.map(|mesh| CachedMesh::new(mesh, arena, device, queue)))
+                .collect(),
+        }
+    }
+
+    /// Replace the chunk's mesh. If it has no lower levels of detail, the
+    /// cached ones are kept.
+    pub fn update(
+        &mut self,
+        mesh: ChunkMesh,
+        arena: &mut ChunkArena,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        if mesh.origin != self.origin || mesh.stats.mesher != self.stats.mesher {
+            arena.instances.realloc(
+                device,
+                queue,
+                &mut self.instance,
+                bytemuck::cast_slice(&[Instance::new(&mesh)]),
+            );
+        }
+
+        self.origin = mesh.origin;
+        self.size = mesh.size;
+        self.visibility = mesh.visibility;
+        self.stats = mesh.stats;
+
+        // without new levels of detail, the cached ones are kept, and so
+        // must stay within bounds
+        if mesh.lods.is_empty() {
+            self.bounds = self.bounds.union(mesh.bounds);
+            for (cached, mesh) in self.lods[0].iter_mut().zip(mesh.layers) {
+                cached.update(mesh, arena, device, queue);
+            }
+            return;
+        }
+
+        self.bounds = mesh.bounds;
+        let mesh_lods = mesh.lods.len();
+
+        let mut lods = std::iter::once(mesh.layers).chain(mesh.lods);
+        for cached in &mut self.lods {
+            let Some(layers) = lods.next() else {
+                break;
+            };
+            for (cached, mesh) in cached.iter_mut().zip(layers) {
+                cached.update(mesh, arena, device, queue);
+            }
+        }
+
+        // the number of levels may have changed
+        let cached = self.lods.len().min(1 + mesh_lods);
+        for layers in self.lods.drain(cached..) {
+            layers.into_iter().for_each(|mesh| mesh.free(arena));
+        }
+        self.lods.extend(
+            lods.map(|layers| layers.map(|mesh| CachedMesh::new(mesh, arena, device, queue))),
+        );
+    }
+
+    /// Return the chunk's allocations to the arena.
+    pub fn free(self, arena: &mut ChunkArena) {
+        arena.instances.free(self.instance);
+        for mesh in self.lods.into_iter().flatten() {
+            mesh.free(arena);
+        }
+    }
+
+    /// The number of bytes reserved for the chunk in the arena.
+    pub fn memory(&self, arena: &ChunkArena) -> u64 {
+        let meshes: u64 = self
+            .lods
+            .iter()
+            .flatten()
+            .map(|mesh| mesh.memory(arena))
+            .sum();
+        meshes + arena.instances.size(&self.instance)
+    }
+
+    /// The statistics of the mesh currently cached.
+    pub fn stats(&self) -> &MeshStats {
+        &self.stats
+    }
+
+    /// The number of levels of detail cached, including full resolution.
+    pub fn lods(&self) -> usize {
+        self.lods.len()
+    }
+
+    /// The world-space bounds of the chunk's mesh.
+    pub fn bounds(&self) -> &Aabb<f32> {
+        &self.bounds
+    }
+
+    /// The position of the chunk, in chunks.
+    pub fn position(&self) -> [i32; 3] {
+        self.origin.map(|c| (c / self.size).round() as i32)
+    }
+
+    /// The world-space center of the chunk.
+    pub fn center(&self) -> [f32; 3] {
+        self.origin.map(|c| c + self.size / 2.0)
+    }
+
+    /// Add the draws for one of the chunk's layers at the given level of
+    /// detail to `draws`, skipping faces that point away from `eye`.
+    ///
+    /// If the level isn't cached, the lowest one that is is used instead.
+    /// Returns the number of indices skipped.
+    pub(super) fn draws(
+        &self,
+        layer: Layer,
+        lod: usize,
+        arena: &ChunkArena,
+        eye: Vec3<f32>,
+        draws: &mut Vec<Draw>,
+    ) -> u32 {
+        let mesh = &self.lods[lod.min(self.lods.len() - 1)][layer as usize];
+        if mesh.num_indices == 0 {
+            return 0;
+        }
+
+        let vertices = arena.vertices.range(&mesh.vertices);
+        let indices = arena.indices[index_slot(mesh.index_format)].range(&mesh.indices);
+        let instance = arena.instances.range(&self.instance);
+        let index_size = match mesh.index_format {
+            wgpu::IndexFormat::Uint16 => 2,
+            wgpu::IndexFormat::Uint32 => 4,
+        };
+
+        let first_index = (indices.start / index_size) as u32;
+        let draw = |range: Range<u32>| Draw {
+            format: mesh.index_format,
+            args: DrawIndexedIndirectArgs {
+                index_count: range.end - range.start,
+                instance_count: 1,
+                first_index: first_index + range.start,
+                base_vertex: (vertices.start / vertex::VERTEX_SIZE as u64) as i32,
+                first_instance: (instance.start / INSTANCE_SIZE) as u32,
+            },
+        };
+
+        draws_facing(&self.bounds, &mesh.directions, eye, |range| {
+            draws.push(draw(range))
+        })
+    }
+}
+
+/// Pass each range of `directions` that has to be drawn for a chunk with the
+/// given bounds to `draw`, skipping faces that point away from `eye`.
+///
+/// Returns the number of indices skipped.
+fn draws_facing(
+    bounds: &Aabb<f32>,
+    directions: &[Range<u32>; FACE_GROUPS],
+    eye: Vec3<f32>,
+    mut draw: impl FnMut(Range<u32>),
+) -> u32 {
+    // the directions are stored one after the other, so neighbouring
+    // directions that are both visible can share a draw. faces that don't
+    // point along an axis come last, and are always drawn
+    let groups = Direction::ALL.map(Some).into_iter().chain([None]);
+    let mut skipped = 0;
+    let mut pending: Option<Range<u32>> = None;
+    for (direction, range) in groups.zip(directions) {
+        if range.is_empty() {
+            continue;
+        }
+
+        if !direction.is_none_or(|direction| faces(bounds, direction, eye)) {
+            skipped += range.end - range.start;
+            if let Some(pending) = pending.take() {
+                draw(pending);
+            }
+            continue;
+        }
+
+        pending = Some(match pending {
+            Some(pending) if pending.end == range.start => pending.start..range.end,
+            Some(pending) => {
+                draw(pending);
+                range.clone()
+            }
+            None => range.clone(),
+        });
+    }
+    if let Some(pending) = pending {
+        draw(pending);
+    }
+
+    skipped
+}
+
+/// Whether any faces within `bounds` pointing in `direction` might face `eye`.
+fn faces(bounds: &Aabb<f32>, direction: Direction, eye: Vec3<f32>) -> bool {
+    let normal = Vec3::from(direction.normal().map(|c| c as f32));
+    // the bound furthest behind the faces
+    let back: Vec3<f32> = (0..3)
+        .map(|i| {
+            if normal[i] > 0.0 {
+                bounds.min[i]
+            } else {
+                bounds.max[i]
+            }
+        })
+        .collect();
+
+    normal.dot(eye - back) > 0.0
+}
 
 pub struct CachedMesh {
-    pub(super) vertices: wgpu::Buffer,
-    pub(super) indices: wgpu::Buffer,
+    pub(super) vertices: Allocation,
+    pub(super) indices: Allocation,
     pub(super) num_indices: u32,
+    pub(super) index_format: wgpu::IndexFormat,
+    /// The indices of the faces pointing in each [`Direction`], followed by
+    /// those pointing elsewhere.
+    pub(super) directions: [Range<u32>; FACE_GROUPS],
 }
 
 impl CachedMesh {
-    pub fn new(mesh: Mesh, device: &mut wgpu::Device) -> Self {
+    pub fn new(
+        mesh: Mesh,
+        arena: &mut ChunkArena,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let num_indices = mesh.indices.len() as u32;
+        let directions = mesh.directions.clone();
         let (vertices, indices) = mesh.finish();
-        let num_indices = indices.len() as u32;
-
-        let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
 
-        let indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-        });
+        let index_format = indices.format();
+        let vertices = arena
+            .vertices
+            .alloc(device, queue, bytemuck::cast_slice(&vertices));
+        let indices = arena.indices[index_slot(index_format)].alloc(device, queue, indices.bytes());
 
         Self {
             vertices,
             indices,
             num_indices,
+            index_format,
+            directions,
         }
     }
 
-    pub fn update(&mut self, mesh: Mesh, device: &mut wgpu::Device, queue: &mut wgpu::Queue) {
+    pub fn update(
+        &mut self,
+        mesh: Mesh,
+        arena: &mut ChunkArena,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        // padding doesn't count
+        self.num_indices = mesh.indices.len() as u32;
+        self.directions = mesh.directions.clone();
         let (vertices, indices) = mesh.finish();
-        self.num_indices = indices.len() as u32;
-
-        if vertices.len() * vertex::VERTEX_SIZE > self.vertices.size() as usize {
-            self.vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            });
+
+        arena.vertices.realloc(
+            device,
+            queue,
+            &mut self.vertices,
+            bytemuck::cast_slice(&vertices),
+        );
+
+        let format = indices.format();
+        if format == self.index_format {
+            arena.indices[index_slot(format)].realloc(
+                device,
+                queue,
+                &mut self.indices,
+                indices.bytes(),
+            );
         } else {
-            let start = self.vertices.as_entire_buffer_binding().offset;
-            queue.write_buffer(&self.vertices, start, bytemuck::cast_slice(&vertices));
+            let new = arena.indices[index_slot(format)].alloc(device, queue, indices.bytes());
+            let old = std::mem::replace(&mut self.indices, new);
+            arena.indices[index_slot(self.index_format)].free(old);
+            self.index_format = format;
         }
+    }
 
-        if indices.len() * 4 > self.indices.size() as usize {
-            self.indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-            });
-        } else {
-            let start = self.indices.as_entire_buffer_binding().offset;
-            queue.write_buffer(&self.indices, start, bytemuck::cast_slice(&indices));
+    fn memory(&self, arena: &ChunkArena) -> u64 {
+        arena.vertices.size(&self.vertices)
+            + arena.indices[index_slot(self.index_format)].size(&self.indices)
+    }
+
+    fn free(self, arena: &mut ChunkArena) {
+        arena.vertices.free(self.vertices);
+        arena.indices[index_slot(self.index_format)].free(self.indices);
+    }
+}
+
+/// Which of the [`ChunkArena`]'s index buffers holds indices of the given
+/// format.
+fn index_slot(format: wgpu::IndexFormat) -> usize {
+    match format {
+        wgpu::IndexFormat::Uint16 => 0,
+        wgpu::IndexFormat::Uint32 => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each draw as `[start, end]`, and the number of indices skipped, for a
+    /// chunk from 0 to 16 along each axis.
+    fn draws(directions: &[Range<u32>; FACE_GROUPS], eye: [f32; 3]) -> (Vec<[u32; 2]>, u32) {
+        let bounds = Aabb {
+            min: Vec3::zero(),
+            max: Vec3::broadcast(16.0),
+        };
+
+        let mut draws = Vec::new();
+        let skipped = draws_facing(&bounds, directions, eye.into(), |range| {
+            draws.push([range.start, range.end])
+        });
+        (draws, skipped)
+    }
+
+    /// Six indices in each direction group.
+    fn full() -> [Range<u32>; FACE_GROUPS] {
+        std::array::from_fn(|group| group as u32 * 6..group as u32 * 6 + 6)
+    }
+
+    #[test]
+    fn skips_the_far_side() {
+        // groups are ordered top, bottom, left, right, front, back, oblique
+        let cases = [
+            ([8.0, 30.0, 8.0], vec![[0, 6], [12, 42]]),
+            ([8.0, -14.0, 8.0], vec![[6, 42]]),
+            ([-14.0, 8.0, 8.0], vec![[0, 18], [24, 42]]),
+            ([30.0, 8.0, 8.0], vec![[0, 12], [18, 42]]),
+            ([8.0, 8.0, 30.0], vec![[0, 30], [36, 42]]),
+            ([8.0, 8.0, -14.0], vec![[0, 24], [30, 42]]),
+        ];
+        for (eye, expected) in cases {
+            assert_eq!(draws(&full(), eye), (expected, 6), "from {eye:?}");
         }
     }
+
+    #[test]
+    fn skips_every_far_side_from_a_corner() {
+        assert_eq!(
+            draws(&full(), [-14.0, 30.0, -14.0]),
+            (vec![[0, 6], [12, 18], [30, 42]], 18)
+        );
+    }
+
+    #[test]
+    fn draws_everything_from_inside() {
+        assert_eq!(draws(&full(), [8.0, 8.0, 8.0]), (vec![[0, 42]], 0));
+    }
+
+    #[test]
+    fn skips_empty_groups() {
+        // only the bottom and oblique faces, with the bottom facing away
+        let mut directions: [Range<u32>; FACE_GROUPS] = Default::default();
+        directions[1] = 0..6;
+        directions[6] = 6..9;
+        assert_eq!(draws(&directions, [8.0, 30.0, 8.0]), (vec![[6, 9]], 6));
+    }
 }
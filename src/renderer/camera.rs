@@ -47,7 +47,11 @@ impl CameraUniform {
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into_col_arrays();
+        self.set_view_proj(camera.build_view_projection_matrix());
+    }
+
+    pub fn set_view_proj(&mut self, view_proj: Mat4<f32>) {
+        self.view_proj = view_proj.into_col_arrays();
     }
 }
 
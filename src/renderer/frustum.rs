@@ -0,0 +1,114 @@
+use vek::{Aabb, Mat4, Vec3, Vec4};
+
+/// The six planes bounding what a camera can see.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// Each plane as `(normal, distance)`, with the normal pointing inwards.
+    planes: [Vec4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extract the frustum from a view-projection matrix, such as the one
+    /// from [`Camera::build_view_projection_matrix`](super::camera::Camera::build_view_projection_matrix).
+    ///
+    /// Clip space is wgpu's, so depth runs from 0 to 1.
+    pub fn from_matrix(view_proj: Mat4<f32>) -> Self {
+        let rows = view_proj.into_row_arrays().map(Vec4::<f32>::from);
+        let [x, y, z, w] = rows;
+
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.xyz().magnitude();
+            plane / length
+        });
+
+        Self { planes }
+    }
+
+    /// Whether any part of a box might be inside the frustum.
+    ///
+    /// Boxes near the frustum's corners may be reported as visible even when
+    /// they aren't, but never the other way round.
+    pub fn intersects(&self, bounds: &Aabb<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            // the corner furthest along the plane's normal
+            let corner: Vec3<f32> = (0..3)
+                .map(|i| {
+                    if normal[i] >= 0.0 {
+                        bounds.max[i]
+                    } else {
+                        bounds.min[i]
+                    }
+                })
+                .collect();
+
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+impl Default for Frustum {
+    /// A frustum containing everything.
+    fn default() -> Self {
+        Self {
+            planes: [Vec4::new(0.0, 0.0, 0.0, 1.0); 6],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::camera::Camera;
+
+    /// The frustum of a camera at the origin looking down -z.
+    fn frustum() -> Frustum {
+        let mut camera = Camera::new([0.0; 3], 1.0);
+        camera.fovy = 1.2;
+        Frustum::from_matrix(camera.build_view_projection_matrix())
+    }
+
+    fn cube(center: [f32; 3], half: f32) -> Aabb<f32> {
+        let center = Vec3::from(center);
+        Aabb {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    #[test]
+    fn contains_boxes_in_view() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&cube([0.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects(&cube([3.0, -3.0, -100.0], 1.0)));
+    }
+
+    #[test]
+    fn rejects_boxes_out_of_view() {
+        let frustum = frustum();
+        // behind, beside, above and beyond the far plane
+        assert!(!frustum.intersects(&cube([0.0, 0.0, 10.0], 1.0)));
+        assert!(!frustum.intersects(&cube([100.0, 0.0, -10.0], 1.0)));
+        assert!(!frustum.intersects(&cube([0.0, 100.0, -10.0], 1.0)));
+        assert!(!frustum.intersects(&cube([0.0, 0.0, -2000.0], 1.0)));
+    }
+
+    #[test]
+    fn keeps_boxes_straddling_a_plane() {
+        let frustum = frustum();
+        // the side planes are about 6.8 blocks from the center at this depth
+        assert!(frustum.intersects(&cube([7.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects(&cube([0.0, -7.0, -10.0], 1.0)));
+        assert!(frustum.intersects(&cube([0.0, 0.0, -1000.0], 5.0)));
+        assert!(frustum.intersects(&cube([0.0, 0.0, 0.0], 1.0)));
+
+        // just past the same plane
+        assert!(!frustum.intersects(&cube([9.0, 0.0, -10.0], 1.0)));
+    }
+
+    #[test]
+    fn default_contains_everything() {
+        let frustum = Frustum::default();
+        assert!(frustum.intersects(&cube([0.0, 0.0, 1e6], 1.0)));
+    }
+}
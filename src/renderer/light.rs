@@ -3,21 +3,40 @@ use bytemuck::{Pod, Zeroable};
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 pub struct LightUniform {
-    pub position: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding: u32,
+    /// The position of a point light, with a `w` of 1, or the direction
+    /// towards a directional light, with a `w` of 0.
+    pub position: [f32; 4],
     pub color: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding2: u32,
+    /// How much of the light's color reaches surfaces facing away from it.
+    pub ambient: f32,
 }
 
 impl LightUniform {
-    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+    const AMBIENT: f32 = 0.2;
+
+    /// A light shining out from `position`.
+    pub fn new([x, y, z]: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            position: [x, y, z, 1.0],
+            color,
+            ambient: Self::AMBIENT,
+        }
+    }
+
+    /// A light infinitely far away, like the sun, shining from `direction`.
+    pub fn directional(direction: [f32; 3], color: [f32; 3]) -> Self {
+        let length = direction.iter().map(|c| c * c).sum::<f32>().sqrt();
+        let [x, y, z] = direction.map(|c| c / length);
         Self {
-            position,
-            _padding: 0,
+            position: [x, y, z, 0.0],
             color,
-            _padding2: 0,
+            ambient: Self::AMBIENT,
         }
     }
+
+    /// The direction towards the light, if it's directional.
+    pub fn direction(&self) -> Option<[f32; 3]> {
+        let [x, y, z, w] = self.position;
+        (w == 0.0).then_some([x, y, z])
+    }
 }
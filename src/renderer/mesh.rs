@@ -1,45 +1,223 @@
 use ahash::HashMapExt;
 use fxhash::FxHashMap;
 
+use std::ops::Range;
+use std::time::Duration;
+
+use vek::{Aabb, Vec3};
+
+use crate::app::voxel::Direction;
+use crate::renderer::occlusion::Visibility;
 use crate::renderer::Vertex;
 
+mod optimize;
+
+/// The number of lower levels of detail each chunk is meshed at, each half
+/// the resolution of the last.
+pub const LOD_LEVELS: usize = 3;
+
+/// The number of groups a mesh's faces are sorted into: one for each
+/// [`Direction`], then one for faces that don't point along an axis.
+pub const FACE_GROUPS: usize = 7;
+
+/// The passes a mesh can be drawn in, in drawing order.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layer {
+    /// Fully opaque faces.
+    Opaque,
+    /// Alpha-blended faces, drawn back-to-front after everything else.
+    Translucent,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 2] = [Layer::Opaque, Layer::Translucent];
+}
+
+/// The algorithm used to generate a mesh.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mesher {
+    #[default]
+    Fast,
+    Greedy,
+    /// Smooth surfaces rather than blocks.
+    Smooth,
+}
+
+/// Statistics about a generated mesh.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub indices: usize,
+    pub quads: usize,
+    pub mesher: Mesher,
+    /// How long the mesher took to run.
+    pub time: Duration,
+}
+
+/// The mesh of a single chunk, split up by [`Layer`].
+#[derive(Debug, Default, Clone)]
+pub struct ChunkMesh {
+    /// The world-space position of the chunk's lowest corner, which vertex
+    /// positions are relative to.
+    pub(crate) origin: [f32; 3],
+    /// The length of each side of the chunk.
+    pub(crate) size: f32,
+    pub(crate) layers: [Mesh; 2],
+    /// The layers of each lower level of detail, or nothing if they haven't
+    /// been generated.
+    pub(crate) lods: Vec<[Mesh; 2]>,
+    /// The world-space bounds of every vertex in the mesh, at any level of
+    /// detail.
+    pub(crate) bounds: Aabb<f32>,
+    /// Which faces of the chunk can see each other.
+    pub(crate) visibility: Visibility,
+    pub(crate) stats: MeshStats,
+}
+
+impl ChunkMesh {
+    pub(crate) fn new(origin: [f32; 3], size: f32, layers: [Mesh; 2]) -> Self {
+        Self {
+            origin,
+            size,
+            layers,
+            lods: Vec::new(),
+            bounds: Aabb::new_empty(origin.into()),
+            visibility: Visibility::ALL,
+            stats: MeshStats::default(),
+        }
+    }
+
+    /// See [`Mesh::optimize`].
+    pub(crate) fn optimize(&mut self) {
+        for mesh in self.layers.iter_mut().chain(self.lods.iter_mut().flatten()) {
+            mesh.optimize();
+        }
+    }
+
+    /// Fill out the mesh's statistics once it's been generated.
+    pub(crate) fn finish(&mut self, mesher: Mesher, time: Duration) {
+        let vertices = self.layers.iter().map(|mesh| mesh.vertices.len()).sum();
+        let indices = self.layers.iter().map(|mesh| mesh.indices.len()).sum();
+
+        let origin = Vec3::from(self.origin);
+        self.bounds = self
+            .layers
+            .iter()
+            .chain(self.lods.iter().flatten())
+            .flat_map(|mesh| &mesh.vertices)
+            .map(|vertex| origin + Vec3::from(vertex.position()))
+            .fold(None, |bounds: Option<Aabb<f32>>, point| {
+                Some(match bounds {
+                    Some(bounds) => bounds.expanded_to_contain_point(point),
+                    None => Aabb::new_empty(point),
+                })
+            })
+            .unwrap_or(Aabb::new_empty(origin));
+
+        self.stats = MeshStats {
+            vertices,
+            indices,
+            // every quad is two triangles
+            quads: indices / 6,
+            mesher,
+            time,
+        };
+    }
+
+    pub fn stats(&self) -> &MeshStats {
+        &self.stats
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Mesh {
     pub(crate) vertices: Vec<Vertex>,
     pub(crate) indices: Vec<u32>,
+    /// The indices of the faces pointing in each [`Direction`], followed by
+    /// those pointing elsewhere.
+    pub(crate) directions: [Range<u32>; FACE_GROUPS],
 }
 
 impl Mesh {
-    pub(crate) fn new() -> Self {
-        Self::default()
+    /// Reorder the triangles to make better use of the GPU's post-transform
+    /// vertex cache.
+    ///
+    /// The vertices are then renumbered in the order they're first used, so
+    /// that fetching them is as sequential as possible.
+    pub(crate) fn optimize(&mut self) {
+        // keep each group of faces together
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for range in &self.directions {
+            let range = range.start as usize..range.end as usize;
+            indices.extend(optimize::optimize(
+                &self.indices[range],
+                self.vertices.len(),
+            ));
+        }
+        self.indices = indices;
+
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        for index in &mut self.indices {
+            let new = &mut remap[*index as usize];
+            if *new == u32::MAX {
+                *new = vertices.len() as u32;
+                vertices.push(self.vertices[*index as usize]);
+            }
+            *index = *new;
+        }
+        self.vertices = vertices;
     }
 
-    pub(crate) fn vertex(&mut self, vertex: Vertex) -> u32 {
-        let i = self.vertices.len() as u32;
-        self.vertices.push(vertex);
-        i
+    pub(crate) fn finish(self) -> (Vec<Vertex>, Indices) {
+        let indices = Indices::new(self.indices, self.vertices.len());
+        (self.vertices, indices)
     }
+}
 
-    pub(crate) fn vertices(&mut self, vertices: impl IntoIterator<Item = Vertex>) {
-        let vertices = vertices.into_iter();
+/// A mesh's indices, in the smallest format that can address every vertex.
+#[derive(Debug, Clone)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub(crate) fn new(indices: Vec<u32>, num_vertices: usize) -> Self {
+        if num_vertices <= u16::MAX as usize + 1 {
+            let mut short: Vec<_> = indices.into_iter().map(|i| i as u16).collect();
+            // buffer writes have to be a multiple of four bytes
+            if short.len() % 2 == 1 {
+                short.push(0);
+            }
+            Self::U16(short)
+        } else {
+            Self::U32(indices)
+        }
+    }
 
-        let (min, max) = vertices.size_hint();
-        self.indices.reserve(max.unwrap_or(min));
-        for vertex in vertices {
-            let i = self.vertex(vertex);
-            self.indices.push(i);
+    pub(crate) fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            Self::U16(_) => wgpu::IndexFormat::Uint16,
+            Self::U32(_) => wgpu::IndexFormat::Uint32,
         }
     }
 
-    pub(crate) fn finish(self) -> (Vec<Vertex>, Vec<u32>) {
-        (self.vertices, self.indices)
+    pub(crate) fn bytes(&self) -> &[u8] {
+        match self {
+            Self::U16(indices) => bytemuck::cast_slice(indices),
+            Self::U32(indices) => bytemuck::cast_slice(indices),
+        }
     }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct DedupMesh {
     pub(crate) vertices: FxHashMap<Vertex, u32>,
-    pub(crate) indices: Vec<u32>,
+    /// Indices of the faces pointing in each [`Direction`], followed by those
+    /// pointing elsewhere.
+    pub(crate) indices: [Vec<u32>; FACE_GROUPS],
     next: u32,
 }
 
@@ -47,7 +225,7 @@ impl DedupMesh {
     pub(crate) fn new() -> Self {
         Self {
             vertices: FxHashMap::with_capacity(10_000),
-            indices: Vec::with_capacity(5000),
+            indices: Default::default(),
             next: 0,
         }
     }
@@ -60,15 +238,21 @@ impl DedupMesh {
         })
     }
 
-    pub(crate) fn vertices(&mut self, vertices: impl IntoIterator<Item = Vertex>) {
-        let vertices = vertices.into_iter();
+    /// Add a quad facing `direction`, emitting its corners in the given order
+    /// as two triangles.
+    pub(crate) fn quad(&mut self, direction: Direction, corners: [Vertex; 4], order: [usize; 6]) {
+        self.push(direction as usize, corners, order);
+    }
 
-        let (min, max) = vertices.size_hint();
-        self.indices.reserve(max.unwrap_or(min));
-        for vertex in vertices {
-            let i = self.vertex(vertex);
-            self.indices.push(i);
-        }
+    /// Add a quad that doesn't face along an axis, so it can't be culled by
+    /// direction.
+    pub(crate) fn oblique_quad(&mut self, corners: [Vertex; 4], order: [usize; 6]) {
+        self.push(FACE_GROUPS - 1, corners, order);
+    }
+
+    fn push(&mut self, group: usize, corners: [Vertex; 4], order: [usize; 6]) {
+        let ids = corners.map(|vertex| self.vertex(vertex));
+        self.indices[group].extend(order.map(|i| ids[i]));
     }
 
     pub(crate) fn into_mesh(self) -> Mesh {
@@ -78,9 +262,34 @@ impl DedupMesh {
             vertices[idx as usize] = vertex;
         }
 
+        let mut indices = Vec::with_capacity(self.indices.iter().map(Vec::len).sum());
+        let directions = self.indices.map(|direction| {
+            let start = indices.len() as u32;
+            indices.extend(direction);
+            start..indices.len() as u32
+        });
+
         Mesh {
             vertices,
-            indices: self.indices,
+            indices,
+            directions,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_smallest_index_format() {
+        let indices = Indices::new(vec![0, 1, 65535], 65536);
+        assert_eq!(indices.format(), wgpu::IndexFormat::Uint16);
+        // padded to a multiple of four bytes
+        assert_eq!(indices.bytes().len(), 8);
+
+        let indices = Indices::new(vec![0, 1, 65536], 65537);
+        assert_eq!(indices.format(), wgpu::IndexFormat::Uint32);
+        assert_eq!(indices.bytes().len(), 12);
+    }
+}
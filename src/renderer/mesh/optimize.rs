@@ -0,0 +1,210 @@
+//! Tom Forsyth's linear-speed vertex cache optimisation.
+//!
+//! Triangles are emitted greedily, always picking the one whose vertices
+//! score highest; vertices score higher the more recently they were used, and
+//! the fewer triangles they have left.
+
+/// The size of the simulated vertex cache.
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+/// The score of vertices used by the last triangle, which is deliberately
+/// lower than the next few to avoid re-using the same edge.
+const LAST_TRI_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+fn vertex_score(cache_pos: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        // no triangles left to use this vertex
+        return -1.0;
+    }
+
+    let cache_score = match cache_pos {
+        None => 0.0,
+        Some(pos) if pos < 3 => LAST_TRI_SCORE,
+        Some(pos) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (pos - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    // boost vertices with few triangles left, to get rid of lone triangles
+    let valence_boost = VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER);
+
+    cache_score + valence_boost
+}
+
+/// Reorder the triangles in `indices` for better vertex cache usage.
+pub fn optimize(indices: &[u32], num_vertices: usize) -> Vec<u32> {
+    let num_tris = indices.len() / 3;
+    if num_tris == 0 {
+        return indices.to_vec();
+    }
+
+    // the triangles using each vertex; the first `remaining[v]` triangles
+    // from `offsets[v]` are the ones that haven't been emitted yet
+    let mut remaining = vec![0u32; num_vertices];
+    for &i in indices {
+        remaining[i as usize] += 1;
+    }
+
+    let mut offsets = vec![0usize; num_vertices];
+    let mut sum = 0;
+    for (offset, &count) in offsets.iter_mut().zip(&remaining) {
+        *offset = sum;
+        sum += count as usize;
+    }
+
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut filled = vec![0usize; num_vertices];
+    for (tri, corners) in indices.chunks_exact(3).enumerate() {
+        for &v in corners {
+            let v = v as usize;
+            adjacency[offsets[v] + filled[v]] = tri as u32;
+            filled[v] += 1;
+        }
+    }
+
+    let mut cache_pos: Vec<Option<usize>> = vec![None; num_vertices];
+    let mut scores: Vec<f32> = remaining
+        .iter()
+        .map(|&count| vertex_score(None, count))
+        .collect();
+
+    let tri_vertices = |tri: usize| &indices[tri * 3..tri * 3 + 3];
+    let mut tri_scores: Vec<f32> = (0..num_tris)
+        .map(|tri| tri_vertices(tri).iter().map(|&v| scores[v as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; num_tris];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(num_tris * 3);
+
+    // every triangle before this one has been emitted
+    let mut first_left = 0;
+
+    let mut best = None;
+    for _ in 0..num_tris {
+        // if none of the cached vertices have triangles left, fall back to
+        // the first triangle that hasn't been emitted; searching them all
+        // for the best score would take quadratic time, and meshes of
+        // separate quads fall back on almost every one
+        let tri = best.unwrap_or_else(|| {
+            while emitted[first_left] {
+                first_left += 1;
+            }
+            first_left
+        });
+
+        emitted[tri] = true;
+        output.extend_from_slice(tri_vertices(tri));
+
+        // remove the triangle from its vertices' lists
+        for &v in tri_vertices(tri) {
+            let v = v as usize;
+            let start = offsets[v];
+            let end = start + remaining[v] as usize;
+            let list = &mut adjacency[start..end];
+            let at = list.iter().position(|&t| t as usize == tri).unwrap();
+            list.swap(at, list.len() - 1);
+            remaining[v] -= 1;
+        }
+
+        // move the triangle's vertices to the front of the cache
+        let mut new_cache = tri_vertices(tri).to_vec();
+        new_cache.extend(cache.iter().filter(|v| !tri_vertices(tri).contains(v)));
+        let evicted = new_cache.split_off(new_cache.len().min(CACHE_SIZE));
+        cache = new_cache;
+
+        for &v in &evicted {
+            cache_pos[v as usize] = None;
+        }
+        for (pos, &v) in cache.iter().enumerate() {
+            cache_pos[v as usize] = Some(pos);
+        }
+
+        // rescore every vertex that moved, and their triangles
+        for &v in cache.iter().chain(&evicted) {
+            let v = v as usize;
+            let score = vertex_score(cache_pos[v], remaining[v]);
+            let delta = score - scores[v];
+            scores[v] = score;
+
+            let start = offsets[v];
+            for &t in &adjacency[start..start + remaining[v] as usize] {
+                tri_scores[t as usize] += delta;
+            }
+        }
+
+        best = cache
+            .iter()
+            .flat_map(|&v| {
+                let start = offsets[v as usize];
+                &adjacency[start..start + remaining[v as usize] as usize]
+            })
+            .map(|&t| t as usize)
+            .max_by(|&a, &b| tri_scores[a].total_cmp(&tri_scores[b]));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each triangle, rotated to start at its lowest index so that winding
+    /// is kept, in sorted order.
+    fn triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<_> = indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let mut tri = [tri[0], tri[1], tri[2]];
+                let lowest = (0..3).min_by_key(|&i| tri[i]).unwrap();
+                tri.rotate_left(lowest);
+                tri
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    /// A grid of `size` by `size` quads sharing their corners, split into
+    /// triangles alternately wound each way.
+    fn grid(size: u32) -> Vec<u32> {
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let [a, b, c, d] = [[x, y], [x + 1, y], [x + 1, y + 1], [x, y + 1]]
+                    .map(|[x, y]| y * (size + 1) + x);
+                if (x + y) % 2 == 0 {
+                    indices.extend([a, b, c, c, d, a]);
+                } else {
+                    indices.extend([c, b, a, a, d, c]);
+                }
+            }
+        }
+        indices
+    }
+
+    #[test]
+    fn reorders_shared_triangles() {
+        let indices = grid(40);
+        let optimized = optimize(&indices, 41 * 41);
+        assert_eq!(triangles(&optimized), triangles(&indices));
+        assert_ne!(optimized, indices);
+    }
+
+    #[test]
+    fn keeps_separate_triangles() {
+        // nothing is shared, so every triangle is a fallback
+        let indices: Vec<u32> = (0..30_000).rev().collect();
+        let optimized = optimize(&indices, 30_000);
+        assert_eq!(triangles(&optimized), triangles(&indices));
+    }
+
+    #[test]
+    fn keeps_empty_meshes() {
+        assert!(optimize(&[], 0).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use super::mesh::Layer;
+
+/// How terrain is drawn: as it looks, or colored by something else to debug
+/// the renderer.
+///
+/// Every mode but [`Shaded`](Self::Shaded), [`Wireframe`](Self::Wireframe)
+/// and [`Overlay`](Self::Overlay) draws every face opaque, against black.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RenderMode {
+    #[default]
+    Shaded,
+    /// Only the edges of triangles, shaded.
+    Wireframe,
+    /// Shaded faces with the edges of their triangles drawn over them.
+    Overlay,
+    /// Faces colored by their normals.
+    Normals,
+    /// Faces colored at random by chunk.
+    Chunks,
+    /// Faces colored by the [`Mesher`](super::mesh::Mesher) that meshed their
+    /// chunk: red for fast, green for greedy and blue for smooth.
+    Meshers,
+    /// The number of times each pixel is drawn to, brightening from red to
+    /// yellow to white.
+    Overdraw,
+    /// Faces colored by the light reaching them: blue for skylight and
+    /// orange for block light.
+    Light,
+}
+
+/// Everything that differs between the pipelines terrain is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct PipelineConfig {
+    /// The fragment shader's entry point.
+    pub(super) entry_point: &'static str,
+    pub(super) polygon_mode: wgpu::PolygonMode,
+    pub(super) blend: Option<wgpu::BlendState>,
+    pub(super) depth_write: bool,
+    pub(super) depth_compare: wgpu::CompareFunction,
+}
+
+impl PipelineConfig {
+    /// How a layer is drawn as it looks.
+    fn shaded(layer: Layer) -> Self {
+        let opaque = Self {
+            entry_point: "main",
+            polygon_mode: wgpu::PolygonMode::Fill,
+            blend: None,
+            depth_write: true,
+            depth_compare: wgpu::CompareFunction::Less,
+        };
+
+        match layer {
+            Layer::Opaque => opaque,
+            Layer::Translucent => Self {
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
+                }),
+                depth_write: false,
+                ..opaque
+            },
+        }
+    }
+
+    /// Every face drawn opaque by another entry point.
+    fn debug(entry_point: &'static str) -> Self {
+        Self {
+            entry_point,
+            ..Self::shaded(Layer::Opaque)
+        }
+    }
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 8] = [
+        RenderMode::Shaded,
+        RenderMode::Wireframe,
+        RenderMode::Overlay,
+        RenderMode::Normals,
+        RenderMode::Chunks,
+        RenderMode::Meshers,
+        RenderMode::Overdraw,
+        RenderMode::Light,
+    ];
+
+    /// The mode after this one, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&mode| mode == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// Whether any triangles are drawn as wireframes.
+    pub fn wireframe(self) -> bool {
+        matches!(self, RenderMode::Wireframe | RenderMode::Overlay)
+    }
+
+    /// Whether terrain is drawn as it looks, in front of the sky.
+    pub fn shaded(self) -> bool {
+        matches!(
+            self,
+            RenderMode::Shaded | RenderMode::Wireframe | RenderMode::Overlay
+        )
+    }
+
+    /// The pipelines a layer is drawn with, one after another.
+    pub(super) fn pipelines(self, layer: Layer) -> Vec<PipelineConfig> {
+        let shaded = PipelineConfig::shaded(layer);
+
+        match self {
+            RenderMode::Shaded => vec![shaded],
+            RenderMode::Wireframe => vec![PipelineConfig {
+                polygon_mode: wgpu::PolygonMode::Line,
+                ..shaded
+            }],
+            RenderMode::Overlay => vec![
+                shaded,
+                // the edges lie exactly on the faces just drawn
+                PipelineConfig {
+                    entry_point: "wireframe",
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    blend: None,
+                    depth_write: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                },
+            ],
+            RenderMode::Normals => vec![PipelineConfig::debug("normals")],
+            RenderMode::Chunks => vec![PipelineConfig::debug("chunks")],
+            RenderMode::Meshers => vec![PipelineConfig::debug("meshers")],
+            RenderMode::Light => vec![PipelineConfig::debug("light_levels")],
+            // add up every fragment, hidden or not
+            RenderMode::Overdraw => vec![PipelineConfig {
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                depth_write: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                ..PipelineConfig::debug("overdraw")
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::shader::Shaders;
+
+    /// The fragment entry points of the built-in fragment shader, with or
+    /// without its optional features.
+    fn entry_points(features: bool) -> Vec<String> {
+        let mut shaders = Shaders::default();
+        if !features {
+            shaders.features.clear();
+        }
+        let source = shaders.source("frag.wgsl").unwrap();
+        let module = naga::front::wgsl::parse_str(&source).unwrap();
+        module
+            .entry_points
+            .into_iter()
+            .filter(|entry| entry.stage == naga::ShaderStage::Fragment)
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    #[test]
+    fn every_mode_has_valid_pipelines() {
+        for features in [true, false] {
+            let entry_points = entry_points(features);
+            for mode in RenderMode::ALL {
+                for layer in Layer::ALL {
+                    let pipelines = mode.pipelines(layer);
+                    assert!(
+                        !pipelines.is_empty(),
+                        "{mode:?} draws nothing for {layer:?}"
+                    );
+
+                    for config in pipelines {
+                        assert!(
+                            entry_points.iter().any(|name| name == config.entry_point),
+                            "{mode:?} uses {:?}, which isn't in the shader",
+                            config.entry_point
+                        );
+                        // blended fragments mustn't hide what's behind them
+                        if config.blend.is_some() {
+                            assert!(!config.depth_write, "{mode:?} blends {layer:?}");
+                        }
+                        // only wireframes are drawn as lines
+                        let wireframe =
+                            mode == RenderMode::Wireframe || config.entry_point == "wireframe";
+                        assert_eq!(
+                            config.polygon_mode == wgpu::PolygonMode::Line,
+                            wireframe,
+                            "{mode:?} draws {layer:?} with {:?}",
+                            config.polygon_mode
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_shaded_modes_blend_translucent_layers() {
+        for mode in RenderMode::ALL {
+            let opaque = mode.pipelines(Layer::Opaque);
+            let translucent = mode.pipelines(Layer::Translucent);
+            if mode.shaded() {
+                assert!(opaque[0].blend.is_none(), "{mode:?}");
+                assert!(translucent[0].blend.is_some(), "{mode:?}");
+            } else {
+                assert_eq!(opaque, translucent, "{mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn cycles_through_every_mode() {
+        let mut mode = RenderMode::default();
+        for expected in RenderMode::ALL
+            .iter()
+            .cycle()
+            .skip(1)
+            .take(RenderMode::ALL.len())
+        {
+            mode = mode.next();
+            assert_eq!(mode, *expected);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use vek::{Aabb, Vec3};
+
+use super::frustum::Frustum;
+use crate::app::voxel::Direction;
+
+/// Which faces of a chunk can be seen from which others, through the chunk's
+/// non-opaque blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Visibility(u64);
+
+impl Visibility {
+    /// No face can see any other.
+    pub const NONE: Self = Self(0);
+    /// Every face can see every other.
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    /// Mark two faces as able to see each other.
+    pub fn connect(&mut self, a: Direction, b: Direction) {
+        self.0 |= bit(a, b) | bit(b, a);
+    }
+
+    /// Whether one face can be seen from another.
+    pub fn connects(self, a: Direction, b: Direction) -> bool {
+        self.0 & bit(a, b) != 0
+    }
+}
+
+impl Default for Visibility {
+    /// Without knowing what's in a chunk, assume it hides nothing.
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+const fn bit(a: Direction, b: Direction) -> u64 {
+    1 << (a as u64 * 6 + b as u64)
+}
+
+/// Find the chunks that might be visible from `eye`, by flood-filling out from
+/// the chunk it's in. `chunks` holds the [`Visibility`] of every loaded chunk,
+/// each `size` blocks across.
+///
+/// The fill only leaves a chunk through faces that can be seen from the face
+/// it entered through, never doubles back on a direction it's already
+/// travelled in, and skips chunks outside the frustum.
+///
+/// Returns `None` if `eye` isn't inside any of the chunks, in which case
+/// nothing can be culled.
+pub fn visible_chunks(
+    chunks: &HashMap<[i32; 3], Visibility>,
+    size: f32,
+    eye: Vec3<f32>,
+    frustum: &Frustum,
+) -> Option<HashSet<[i32; 3]>> {
+    let start = eye.map(|c| (c / size).floor() as i32).into_array();
+    chunks.get(&start)?;
+
+    let mut visible = HashSet::from([start]);
+    // the position, the face it was entered through, and the directions
+    // travelled to get there
+    let mut queue = VecDeque::from([(start, None::<Direction>, 0u8)]);
+
+    while let Some((pos, entered, travelled)) = queue.pop_front() {
+        let visibility = chunks[&pos];
+
+        for direction in Direction::ALL {
+            if travelled & 1 << direction.opposite() as u8 != 0 {
+                continue;
+            }
+
+            if entered.is_some_and(|entered| !visibility.connects(entered, direction)) {
+                continue;
+            }
+
+            let normal = direction.normal();
+            let next = [0, 1, 2].map(|i| pos[i] + normal[i]);
+            if visible.contains(&next) {
+                continue;
+            }
+
+            if !chunks.contains_key(&next) {
+                continue;
+            }
+
+            let min = Vec3::from(next.map(|c| c as f32 * size));
+            let extent = Aabb {
+                min,
+                max: min + size,
+            };
+            if !frustum.intersects(&extent) {
+                continue;
+            }
+
+            visible.insert(next);
+            queue.push_back((
+                next,
+                Some(direction.opposite()),
+                travelled | 1 << direction as u8,
+            ));
+        }
+    }
+
+    Some(visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::Mat4;
+
+    use super::*;
+    use crate::renderer::camera::opengl_to_wgpu_matrix;
+    use Direction::*;
+
+    fn connecting(pairs: &[(Direction, Direction)]) -> Visibility {
+        let mut visibility = Visibility::NONE;
+        for &(a, b) in pairs {
+            visibility.connect(a, b);
+        }
+        visibility
+    }
+
+    /// The chunks visible from the middle of chunk `[0, 0, 0]`, in a row of
+    /// three along x with `middle` in the middle, and one more on top of it.
+    fn visible(middle: Visibility) -> Vec<[i32; 3]> {
+        let chunks = HashMap::from([
+            ([0, 0, 0], Visibility::ALL),
+            ([1, 0, 0], middle),
+            ([2, 0, 0], Visibility::ALL),
+            ([1, 1, 0], Visibility::ALL),
+        ]);
+        let eye = Vec3::new(8.0, 8.0, 8.0);
+
+        let mut visible: Vec<_> = visible_chunks(&chunks, 16.0, eye, &Frustum::default())
+            .unwrap()
+            .into_iter()
+            .collect();
+        visible.sort();
+        visible
+    }
+
+    #[test]
+    fn sealed_chunks_block_the_fill() {
+        assert_eq!(visible(Visibility::NONE), [[0, 0, 0], [1, 0, 0]]);
+    }
+
+    #[test]
+    fn fills_through_connected_faces() {
+        let straight = connecting(&[(Left, Right)]);
+        assert_eq!(visible(straight), [[0, 0, 0], [1, 0, 0], [2, 0, 0]]);
+
+        let bent = connecting(&[(Left, Top)]);
+        assert_eq!(visible(bent), [[0, 0, 0], [1, 0, 0], [1, 1, 0]]);
+
+        assert_eq!(
+            visible(Visibility::ALL),
+            [[0, 0, 0], [1, 0, 0], [1, 1, 0], [2, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn skips_chunks_outside_the_frustum() {
+        let chunks = HashMap::from([([0, 0, 0], Visibility::ALL), ([1, 0, 0], Visibility::ALL)]);
+        let eye = Vec3::new(8.0, 8.0, 8.0);
+        // looking down -x, away from the second chunk
+        let view_proj = opengl_to_wgpu_matrix()
+            * Mat4::perspective_rh_zo(1.2, 1.0, 0.1, 1000.0)
+            * Mat4::look_at_rh(eye, eye - Vec3::unit_x(), Vec3::unit_y());
+
+        let visible = visible_chunks(&chunks, 16.0, eye, &Frustum::from_matrix(view_proj));
+        assert_eq!(visible, Some(HashSet::from([[0, 0, 0]])));
+    }
+
+    #[test]
+    fn culls_nothing_outside_the_world() {
+        let chunks = HashMap::from([([0, 0, 0], Visibility::ALL)]);
+        let eye = Vec3::new(-8.0, 8.0, 8.0);
+        assert_eq!(
+            visible_chunks(&chunks, 16.0, eye, &Frustum::default()),
+            None
+        );
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::ensure;
+use bytemuck::{Pod, Zeroable};
+use image::{Rgba, RgbaImage};
+use winit::dpi::PhysicalSize;
+
+/// The format frames are drawn in, before any effects. Lit colors can be
+/// brighter than white, until they're tone mapped.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// How many times smaller than the frame bloom is blurred at.
+const BLOOM_DOWNSCALE: u32 = 4;
+/// The width, height and depth of the table color grading starts with,
+/// which leaves every color as it is.
+const IDENTITY_LUT_SIZE: u32 = 16;
+
+/// A full-screen pass run over each frame after it's drawn. Effects are run
+/// in the order they're declared in, whatever order they're enabled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PostEffect {
+    /// Light bleeding out of anything brighter than the threshold, such as
+    /// glowing blocks and the sun.
+    Bloom,
+    /// Colors scaled by the exposure and brought into range by a filmic
+    /// curve, rather than clipped.
+    ToneMapping,
+    /// Colors looked up in a table, set with
+    /// [`Renderer::set_color_grading`](super::Renderer::set_color_grading).
+    ColorGrading,
+    /// Colors encoded with the gamma set, in place of the standard sRGB
+    /// curve.
+    Gamma,
+    /// Fast approximate anti-aliasing, smoothing jagged edges.
+    Fxaa,
+}
+
+impl PostEffect {
+    pub const ALL: [PostEffect; 5] = [
+        PostEffect::Bloom,
+        PostEffect::ToneMapping,
+        PostEffect::ColorGrading,
+        PostEffect::Gamma,
+        PostEffect::Fxaa,
+    ];
+
+    /// The post shader's entry point for the effect's last pass.
+    fn entry_point(self) -> &'static str {
+        match self {
+            PostEffect::Bloom => "bloom",
+            PostEffect::ToneMapping => "tone_mapping",
+            PostEffect::ColorGrading => "color_grading",
+            PostEffect::Gamma => "gamma",
+            PostEffect::Fxaa => "fxaa",
+        }
+    }
+}
+
+/// Which effects are run over each frame, and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostSettings {
+    pub effects: BTreeSet<PostEffect>,
+    /// What colors are multiplied by before tone mapping.
+    pub exposure: f32,
+    /// The gamma frames are encoded with, for displays that need it.
+    pub gamma: f32,
+    /// How bright a color has to be to bloom, with 1 being white.
+    pub bloom_threshold: f32,
+    /// How much of the bloom is added back to the frame.
+    pub bloom_strength: f32,
+}
+
+impl Default for PostSettings {
+    /// Every effect, with a gamma close to sRGB's.
+    fn default() -> Self {
+        Self {
+            effects: PostEffect::ALL.into_iter().collect(),
+            exposure: 1.0,
+            gamma: 2.2,
+            bloom_threshold: 1.0,
+            bloom_strength: 0.6,
+        }
+    }
+}
+
+/// What the post shader needs to know, laid out for it.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct PostUniform {
+    exposure: f32,
+    gamma: f32,
+    bloom_threshold: f32,
+    bloom_strength: f32,
+    /// Whether the frame has been gamma encoded by the time it's output.
+    encoded: u32,
+    /// Whether the target encodes whatever's written to it as sRGB.
+    srgb_target: u32,
+    _padding: [u32; 2],
+}
+
+/// The textures the chain draws into, which are sized to the frame.
+struct Targets {
+    /// The frame as drawn, and another like it, which each effect takes
+    /// turns drawing from one into the other.
+    frames: [wgpu::TextureView; 2],
+    /// The bright parts of the frame, blurred back and forth between the
+    /// two.
+    bloom: [wgpu::TextureView; 2],
+    /// Reading from each of `frames`.
+    frame_sources: [wgpu::BindGroup; 2],
+    /// Reading from each of `bloom`.
+    bloom_sources: [wgpu::BindGroup; 2],
+    /// Binding each of `bloom` to be added to the frame.
+    bloom_bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// Takes frames drawn in HDR to the target through a chain of full-screen
+/// passes, one for each effect enabled, and a last one copying the result to
+/// the target.
+pub struct PostChain {
+    pub settings: PostSettings,
+    size: PhysicalSize<u32>,
+    source_layout: wgpu::BindGroupLayout,
+    bloom_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    /// Every pass's pipeline, by entry point.
+    pipelines: HashMap<&'static str, wgpu::RenderPipeline>,
+    buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    lut: wgpu::TextureView,
+    targets: Targets,
+}
+
+impl PostChain {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+        shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(2, wgpu::TextureViewDimension::D3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Post Source Bind Group Layout"),
+        });
+        let bloom_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0, wgpu::TextureViewDimension::D2)],
+            label: Some("Post Bloom Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post"),
+            bind_group_layouts: &[&source_layout, &bloom_layout],
+            push_constant_ranges: &[],
+        });
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Buffer"),
+            size: std::mem::size_of::<PostUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let lut = create_lut(device, queue, &identity_lut()).expect("the identity table is valid");
+
+        let pipelines = Self::create_pipelines(device, &layout, format, shader);
+        let targets = create_targets(
+            device,
+            size,
+            &source_layout,
+            &bloom_layout,
+            &sampler,
+            &lut,
+            &buffer,
+        );
+
+        Self {
+            settings: PostSettings::default(),
+            size,
+            source_layout,
+            bloom_layout,
+            layout,
+            pipelines,
+            buffer,
+            sampler,
+            lut,
+            targets,
+        }
+    }
+
+    /// Build every pass's pipeline from `shader`, the last drawing to the
+    /// target in `format` and the rest to textures in [`HDR_FORMAT`].
+    pub(super) fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader: &wgpu::ShaderModule,
+    ) -> HashMap<&'static str, wgpu::RenderPipeline> {
+        let passes = ["bloom_extract", "blur_horizontal", "blur_vertical"]
+            .into_iter()
+            .chain(PostEffect::ALL.map(PostEffect::entry_point))
+            .map(|entry_point| (entry_point, HDR_FORMAT))
+            .chain([("output", format)]);
+
+        passes
+            .map(|(entry_point, format)| {
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(&format!("Post {entry_point} render")),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: shader,
+                        entry_point: "vs_main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: shader,
+                        entry_point,
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                });
+                (entry_point, pipeline)
+            })
+            .collect()
+    }
+
+    pub(super) fn layout(&self) -> &wgpu::PipelineLayout {
+        &self.layout
+    }
+
+    pub(super) fn set_pipelines(&mut self, pipelines: HashMap<&'static str, wgpu::RenderPipeline>) {
+        self.pipelines = pipelines;
+    }
+
+    /// The texture each frame is drawn into before any effects.
+    pub(super) fn frame(&self) -> &wgpu::TextureView {
+        &self.targets.frames[0]
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.size = size;
+        self.rebuild_targets(device);
+    }
+
+    /// Set the table colors are looked up in, laid out as a strip of square
+    /// slices: red increases to the right across each slice, green down it,
+    /// and blue from one slice to the next. A table of `n` entries along each
+    /// axis is `n * n` pixels wide and `n` high.
+    pub fn set_color_grading(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lut: Option<&RgbaImage>,
+    ) -> anyhow::Result<()> {
+        self.lut = match lut {
+            Some(lut) => create_lut(device, queue, lut)?,
+            None => create_lut(device, queue, &identity_lut())?,
+        };
+        self.rebuild_targets(device);
+        Ok(())
+    }
+
+    fn rebuild_targets(&mut self, device: &wgpu::Device) {
+        self.targets = create_targets(
+            device,
+            self.size,
+            &self.source_layout,
+            &self.bloom_layout,
+            &self.sampler,
+            &self.lut,
+            &self.buffer,
+        );
+    }
+
+    /// Run the enabled effects over the frame, and copy it to `target`.
+    ///
+    /// Unless `shaded` is set, the frame is copied as it is, keeping the
+    /// colors of debug views exact.
+    pub(super) fn run(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        target: &wgpu::TextureView,
+        srgb_target: bool,
+        shaded: bool,
+    ) {
+        let effects: Vec<_> = if shaded {
+            self.settings.effects.iter().copied().collect()
+        } else {
+            Vec::new()
+        };
+
+        let settings = &self.settings;
+        let uniform = PostUniform {
+            exposure: settings.exposure,
+            gamma: settings.gamma,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_strength: settings.bloom_strength,
+            encoded: effects.contains(&PostEffect::Gamma).into(),
+            srgb_target: srgb_target.into(),
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        // a pass can't read from the texture it draws to, so every pass binds
+        // a bloom texture it isn't drawing to, whether it uses it or not
+        let targets = &self.targets;
+        let mut current = 0;
+        for effect in effects {
+            if effect == PostEffect::Bloom {
+                let source = &targets.frame_sources[current];
+                self.pass(encoder, "bloom_extract", source, 1, &targets.bloom[0]);
+                let source = &targets.bloom_sources[0];
+                self.pass(encoder, "blur_horizontal", source, 0, &targets.bloom[1]);
+                let source = &targets.bloom_sources[1];
+                self.pass(encoder, "blur_vertical", source, 1, &targets.bloom[0]);
+            }
+
+            let source = &targets.frame_sources[current];
+            let target = &targets.frames[1 - current];
+            self.pass(encoder, effect.entry_point(), source, 0, target);
+            current = 1 - current;
+        }
+
+        let source = &targets.frame_sources[current];
+        self.pass(encoder, "output", source, 0, target);
+    }
+
+    fn pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        entry_point: &str,
+        source: &wgpu::BindGroup,
+        bloom: usize,
+        target: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        pass.set_pipeline(&self.pipelines[entry_point]);
+        pass.set_bind_group(0, source, &[]);
+        pass.set_bind_group(1, &self.targets.bloom_bind_groups[bloom], &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// A table that leaves every color as it is.
+fn identity_lut() -> RgbaImage {
+    let size = IDENTITY_LUT_SIZE;
+    let scale = |value: u32| (value * 255 / (size - 1)) as u8;
+    RgbaImage::from_fn(size * size, size, |x, y| {
+        Rgba([scale(x % size), scale(y), scale(x / size), 255])
+    })
+}
+
+/// Turn a strip of slices into a 3D texture.
+fn create_lut(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    strip: &RgbaImage,
+) -> anyhow::Result<wgpu::TextureView> {
+    let size = strip.height();
+    ensure!(
+        size >= 2 && strip.width() == size * size,
+        "a color grading table has to be as many square slices as it is high, not {:?}",
+        strip.dimensions()
+    );
+
+    let texels = lut_texels(strip);
+    let extent = wgpu::Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: size,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Color grading table"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        // the table maps encoded colors to encoded colors, which are
+        // interpolated between as they are
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        &texels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * size),
+            rows_per_image: Some(size),
+        },
+        extent,
+    );
+
+    Ok(texture.create_view(&Default::default()))
+}
+
+/// The texels of the 3D texture a strip of slices becomes, red along x,
+/// green along y and blue along z.
+fn lut_texels(strip: &RgbaImage) -> Vec<u8> {
+    let size = strip.height();
+    let mut texels = Vec::with_capacity((4 * size * size * size) as usize);
+    for blue in 0..size {
+        for green in 0..size {
+            for red in 0..size {
+                texels.extend_from_slice(&strip.get_pixel(blue * size + red, green).0);
+            }
+        }
+    }
+    texels
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    size: PhysicalSize<u32>,
+    source_layout: &wgpu::BindGroupLayout,
+    bloom_layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    lut: &wgpu::TextureView,
+    buffer: &wgpu::Buffer,
+) -> Targets {
+    let texture = |label, width: u32, height: u32| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&Default::default())
+    };
+    let PhysicalSize { width, height } = size;
+    let frames = [(); 2].map(|_| texture("HDR frame", width, height));
+    let bloom =
+        [(); 2].map(|_| texture("Bloom", width / BLOOM_DOWNSCALE, height / BLOOM_DOWNSCALE));
+
+    let source = |view: &wgpu::TextureView| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: source_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("post_source_bind_group"),
+        })
+    };
+    let bloom_bind_group = |view: &wgpu::TextureView| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bloom_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            }],
+            label: Some("post_bloom_bind_group"),
+        })
+    };
+
+    Targets {
+        frame_sources: [source(&frames[0]), source(&frames[1])],
+        bloom_sources: [source(&bloom[0]), source(&bloom[1])],
+        bloom_bind_groups: [bloom_bind_group(&bloom[0]), bloom_bind_group(&bloom[1])],
+        frames,
+        bloom,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_lut_maps_grid_points_to_themselves() {
+        let size = IDENTITY_LUT_SIZE as usize;
+        let texels = lut_texels(&identity_lut());
+        assert_eq!(texels.len(), 4 * size * size * size);
+
+        // the color at each grid point, as the shader samples it
+        let grid = |i: usize| (i * 255 / (size - 1)) as u8;
+        for blue in 0..size {
+            for green in 0..size {
+                for red in 0..size {
+                    let at = 4 * ((blue * size + green) * size + red);
+                    assert_eq!(
+                        texels[at..at + 4],
+                        [grid(red), grid(green), grid(blue), 255],
+                        "at {:?}",
+                        [red, green, blue]
+                    );
+                }
+            }
+        }
+
+        // the table spans the whole range
+        assert_eq!(texels[..3], [0, 0, 0]);
+        assert_eq!(texels[texels.len() - 4..texels.len() - 1], [255, 255, 255]);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+
+/// The files the renderer's shaders are built from, by name.
+const BUILTIN: [(&str, &str); 7] = [
+    ("common.wgsl", include_str!("../shaders/common.wgsl")),
+    ("vert.wgsl", include_str!("../shaders/vert.wgsl")),
+    ("frag.wgsl", include_str!("../shaders/frag.wgsl")),
+    ("sky.wgsl", include_str!("../shaders/sky.wgsl")),
+    ("sky_pass.wgsl", include_str!("../shaders/sky_pass.wgsl")),
+    ("debug.wgsl", include_str!("../shaders/debug.wgsl")),
+    ("post.wgsl", include_str!("../shaders/post.wgsl")),
+];
+
+/// The features turned on unless told otherwise.
+const DEFAULT_FEATURES: [&str; 2] = ["FOG", "SHADOWS"];
+
+/// The WGSL source of the renderer's shaders, and which of their features
+/// are turned on.
+///
+/// Before being compiled, each shader is run through a preprocessor that
+/// understands a few directives, each on a line of its own:
+///
+/// - `#include "file.wgsl"` pastes in another file, unless it's already been
+///   pasted into the same shader; a file can't include itself, even through
+///   others
+/// - `#define NAME` turns a feature on for the rest of the shader
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the
+///   lines between them depending on whether a feature is on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shaders {
+    files: HashMap<String, String>,
+    /// The features defined before any shader starts, such as `FOG` and
+    /// `SHADOWS`.
+    pub features: BTreeSet<String>,
+}
+
+/// The renderer's compiled shaders.
+pub(super) struct Modules {
+    pub(super) vert: wgpu::ShaderModule,
+    pub(super) frag: wgpu::ShaderModule,
+    pub(super) sky: wgpu::ShaderModule,
+    pub(super) post: wgpu::ShaderModule,
+}
+
+/// A shader with its directives carried out, and which line of which file
+/// each of its lines came from.
+#[derive(Default)]
+struct Source {
+    text: String,
+    lines: Vec<(String, usize)>,
+}
+
+impl Default for Shaders {
+    fn default() -> Self {
+        Self {
+            files: BUILTIN
+                .iter()
+                .map(|&(name, source)| (name.to_owned(), source.to_owned()))
+                .collect(),
+            features: DEFAULT_FEATURES
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+        }
+    }
+}
+
+impl Shaders {
+    /// Read every WGSL file in `dir`, in place of any file of the same name.
+    /// Files the renderer doesn't build from are only used if included.
+    pub fn read_dir(&mut self, dir: &Path) -> Result<()> {
+        for entry in std::fs::read_dir(dir).with_context(|| format!("reading {dir:?}"))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "wgsl") {
+                let source =
+                    std::fs::read_to_string(&path).with_context(|| format!("reading {path:?}"))?;
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                debug!("Read shader {name:?} from {dir:?}");
+                self.files.insert(name, source);
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn a feature on or off.
+    pub fn set_feature(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.features.insert(name.to_owned());
+        } else {
+            self.features.remove(name);
+        }
+    }
+
+    /// Carry out the directives in the file `name`, and those of every file
+    /// it includes.
+    fn preprocess(&self, name: &str) -> Result<Source> {
+        let mut source = Source::default();
+        let mut defines = self.features.clone();
+        let mut included = HashSet::new();
+        self.include(
+            name,
+            &mut source,
+            &mut defines,
+            &mut included,
+            &mut Vec::new(),
+        )?;
+        Ok(source)
+    }
+
+    /// Paste in the file `name`, given the files `including` it so far.
+    fn include(
+        &self,
+        name: &str,
+        source: &mut Source,
+        defines: &mut BTreeSet<String>,
+        included: &mut HashSet<String>,
+        including: &mut Vec<String>,
+    ) -> Result<()> {
+        if including.iter().any(|file| file == name) {
+            bail!("{} -> {name}: include cycle", including.join(" -> "));
+        }
+        if !included.insert(name.to_owned()) {
+            return Ok(());
+        }
+        let file = self
+            .files
+            .get(name)
+            .ok_or_else(|| anyhow!("no shader file named {name:?}"))?;
+
+        // whether each enclosing `#ifdef` is keeping its lines, and whether
+        // it's reached its `#else`
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+
+        for (number, line) in file.lines().enumerate() {
+            let at = || format!("{name}:{}", number + 1);
+            let active = conditions.iter().all(|&(keep, _)| keep);
+
+            let Some(directive) = line.trim().strip_prefix('#') else {
+                if active {
+                    source.text.push_str(line);
+                    source.text.push('\n');
+                    source.lines.push((name.to_owned(), number + 1));
+                }
+                continue;
+            };
+
+            let (keyword, argument) = match directive.split_once(char::is_whitespace) {
+                Some((keyword, argument)) => (keyword, argument.trim()),
+                None => (directive, ""),
+            };
+            let named = || {
+                if argument.is_empty() {
+                    Err(anyhow!("{}: `#{keyword}` needs a name", at()))
+                } else {
+                    Ok(argument)
+                }
+            };
+
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let defined = defines.contains(named()?);
+                    conditions.push((defined == (keyword == "ifdef"), false));
+                }
+                "else" => match conditions.last_mut() {
+                    Some((keep, seen_else @ false)) => {
+                        *keep = !*keep;
+                        *seen_else = true;
+                    }
+                    _ => bail!("{}: `#else` without `#ifdef`", at()),
+                },
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        bail!("{}: `#endif` without `#ifdef`", at());
+                    }
+                }
+                // only conditions are followed in lines being dropped
+                _ if !active => {}
+                "define" => {
+                    defines.insert(named()?.to_owned());
+                }
+                "include" => {
+                    let file = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .ok_or_else(|| anyhow!("{}: expected `#include \"file\"`", at()))?;
+                    including.push(name.to_owned());
+                    self.include(file, source, defines, included, including)
+                        .with_context(at)?;
+                    including.pop();
+                }
+                _ => bail!("{}: unknown directive `#{keyword}`", at()),
+            }
+        }
+
+        if !conditions.is_empty() {
+            bail!("{name}: `#ifdef` without `#endif`");
+        }
+        Ok(())
+    }
+
+    /// Preprocess the shader built from the file `name`, and check that it's
+    /// valid.
+    pub(super) fn source(&self, name: &str) -> Result<String> {
+        let source = self.preprocess(name)?;
+        let path = format!("{name} (preprocessed)");
+
+        let (message, location) = match naga::front::wgsl::parse_str(&source.text) {
+            Ok(module) => {
+                let mut validator = naga::valid::Validator::new(
+                    naga::valid::ValidationFlags::all(),
+                    naga::valid::Capabilities::all(),
+                );
+                match validator.validate(&module) {
+                    Ok(_) => return Ok(source.text),
+                    Err(err) => (
+                        err.emit_to_string_with_path(&source.text, &path),
+                        err.location(&source.text),
+                    ),
+                }
+            }
+            Err(err) => (
+                err.emit_to_string_with_path(&source.text, &path),
+                err.location(&source.text),
+            ),
+        };
+
+        // the error refers to lines of the preprocessed shader, which may
+        // have come from any of the files it includes
+        let origin = location.and_then(|location| {
+            let line = location.line_number;
+            let (file, origin) = source.lines.get(line as usize - 1)?;
+            Some(format!("\nline {line} is {file}:{origin}"))
+        });
+        bail!("{message}{}", origin.unwrap_or_default())
+    }
+
+    /// Compile every shader, failing if any of them has errors.
+    pub(super) fn compile(&self, device: &wgpu::Device) -> Result<Modules> {
+        let module = |label, name| -> Result<wgpu::ShaderModule> {
+            Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(self.source(name)?.into()),
+            }))
+        };
+
+        Ok(Modules {
+            vert: module("Vertex Shader", "vert.wgsl")?,
+            frag: module("Fragment Shader", "frag.wgsl")?,
+            sky: module("Sky Shader", "sky_pass.wgsl")?,
+            post: module("Post Shader", "post.wgsl")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shaders(files: &[(&str, &str)]) -> Shaders {
+        Shaders {
+            files: files
+                .iter()
+                .map(|&(name, source)| (name.to_owned(), source.to_owned()))
+                .collect(),
+            features: BTreeSet::from(["FOG".to_owned()]),
+        }
+    }
+
+    fn preprocess(files: &[(&str, &str)]) -> Result<String> {
+        Ok(shaders(files).preprocess(files[0].0)?.text)
+    }
+
+    fn error(files: &[(&str, &str)]) -> String {
+        format!("{:#}", preprocess(files).unwrap_err())
+    }
+
+    #[test]
+    fn includes_each_file_once() {
+        let text = preprocess(&[
+            (
+                "main.wgsl",
+                "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain",
+            ),
+            ("a.wgsl", "#include \"b.wgsl\"\na"),
+            ("b.wgsl", "b"),
+        ]);
+        assert_eq!(text.unwrap(), "b\na\nmain\n");
+
+        let source = shaders(&[("main.wgsl", "#include \"a.wgsl\"\nmain"), ("a.wgsl", "a")])
+            .preprocess("main.wgsl")
+            .unwrap();
+        let lines = [("a.wgsl".to_owned(), 1), ("main.wgsl".to_owned(), 2)];
+        assert_eq!(source.lines, lines);
+    }
+
+    #[test]
+    fn keeps_lines_by_feature() {
+        let text = preprocess(&[(
+            "main.wgsl",
+            "#ifdef FOG\nfog\n#else\nclear\n#endif\n\
+             #ifndef SHADOWS\nlit\n#endif\n\
+             #define SHADOWS\n\
+             #ifdef SHADOWS\n  #ifndef FOG\n  hidden\n  #endif\nshadows\n#endif",
+        )]);
+        assert_eq!(text.unwrap(), "fog\nlit\nshadows\n");
+    }
+
+    #[test]
+    fn ignores_directives_in_dropped_lines() {
+        let text = preprocess(&[(
+            "main.wgsl",
+            "#ifndef FOG\n#define SHADOWS\n#include \"missing.wgsl\"\n#endif\n\
+             #ifdef SHADOWS\nshadows\n#endif",
+        )]);
+        assert_eq!(text.unwrap(), "");
+    }
+
+    #[test]
+    fn rejects_unbalanced_conditions() {
+        let unterminated = error(&[("main.wgsl", "#ifdef FOG\nfog")]);
+        assert_eq!(unterminated, "main.wgsl: `#ifdef` without `#endif`");
+
+        let stray = error(&[("main.wgsl", "fog\n#endif")]);
+        assert_eq!(stray, "main.wgsl:2: `#endif` without `#ifdef`");
+
+        let twice = error(&[("main.wgsl", "#ifdef FOG\n#else\n#else\n#endif")]);
+        assert_eq!(twice, "main.wgsl:3: `#else` without `#ifdef`");
+    }
+
+    #[test]
+    fn rejects_include_cycles() {
+        let cycle = error(&[
+            ("main.wgsl", "#include \"a.wgsl\""),
+            ("a.wgsl", "#include \"b.wgsl\""),
+            ("b.wgsl", "#include \"main.wgsl\""),
+        ]);
+        assert!(cycle.ends_with("main.wgsl -> a.wgsl -> b.wgsl -> main.wgsl: include cycle"));
+    }
+
+    #[test]
+    fn rejects_missing_includes() {
+        let missing = error(&[("main.wgsl", "\n#include \"a.wgsl\"")]);
+        assert_eq!(missing, "main.wgsl:2: no shader file named \"a.wgsl\"");
+    }
+}
@@ -0,0 +1,175 @@
+use bytemuck::{Pod, Zeroable};
+use vek::{FrustumPlanes, Mat4, Vec3};
+
+use super::texture::Texture;
+use super::vertex::{Instance, Vertex};
+
+/// The width and height of the shadow map, in texels.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// What the fragment shader needs to look up shadows.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ShadowUniform {
+    /// Transforms world space into the shadow map's clip space. Laid out
+    /// like [`CameraUniform`](super::camera::CameraUniform), so that the
+    /// shadow pass can draw with it as its camera.
+    pub view_proj: [[f32; 4]; 4],
+    /// Whether the shadow map holds anything; point lights don't cast
+    /// shadows.
+    pub enabled: u32,
+    pub _padding: [u32; 3],
+}
+
+/// A depth map of the world as seen by a directional light, covering the
+/// area around the camera.
+pub struct ShadowMap {
+    pub(super) texture: Texture,
+    pub(super) buffer: wgpu::Buffer,
+    /// Binds the light's view as the camera of the shadow pass.
+    pub(super) camera_bind_group: wgpu::BindGroup,
+    /// Stands in for the light's bind group, which holds the shadow map and
+    /// so can't be bound while drawing to it.
+    pub(super) empty_bind_group: wgpu::BindGroup,
+    layout: wgpu::PipelineLayout,
+    pub(super) pipeline: wgpu::RenderPipeline,
+    /// How far from the camera shadows are drawn, in blocks.
+    pub distance: f32,
+}
+
+impl ShadowMap {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        palette_layout: &wgpu::BindGroupLayout,
+        vert_shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let texture = Texture::create_shadow_map(device, SHADOW_MAP_SIZE, "Shadow map");
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("shadow_camera_bind_group"),
+        });
+
+        let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[],
+            label: Some("Empty Bind Group Layout"),
+        });
+        let empty_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &empty_layout,
+            entries: &[],
+            label: Some("empty_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow"),
+            bind_group_layouts: &[camera_layout, &empty_layout, palette_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &layout, vert_shader);
+
+        Self {
+            texture,
+            buffer,
+            camera_bind_group,
+            empty_bind_group,
+            layout,
+            pipeline,
+            distance: 128.0,
+        }
+    }
+
+    /// Build the shadow pass's pipeline from another vertex shader.
+    pub(super) fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        vert_shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        create_pipeline(device, &self.layout, vert_shader)
+    }
+
+    /// Work out the view of a light shining from `direction` that covers
+    /// everything within [`distance`](Self::distance) of `eye`.
+    ///
+    /// The view only ever moves a whole texel at a time, so that the edges
+    /// of shadows don't shimmer as the camera moves.
+    pub fn view_proj(&self, eye: Vec3<f32>, direction: Vec3<f32>) -> Mat4<f32> {
+        let direction = direction.normalized();
+        let up = if direction.y.abs() > 0.99 {
+            Vec3::unit_z()
+        } else {
+            Vec3::unit_y()
+        };
+        let view = Mat4::look_at_rh(Vec3::zero(), -direction, up);
+
+        let radius = self.distance;
+        let texel = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+        let center = view.mul_point(eye).map(|c| (c / texel).floor() * texel);
+
+        // the view looks down -z; reach well back towards the light to catch
+        // anything casting shadows into the area
+        let proj = Mat4::orthographic_rh_zo(FrustumPlanes {
+            left: center.x - radius,
+            right: center.x + radius,
+            bottom: center.y - radius,
+            top: center.y + radius,
+            near: -center.z - 2.0 * radius,
+            far: -center.z + radius,
+        });
+
+        proj * view
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vert_shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow render"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: vert_shader,
+            entry_point: "main",
+            buffers: &[Vertex::desc(), Instance::desc()],
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            // push the depths back a little, so that surfaces don't
+            // shadow themselves
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
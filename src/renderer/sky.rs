@@ -0,0 +1,146 @@
+use bytemuck::{Pod, Zeroable};
+use vek::{Mat4, Vec3};
+
+use super::texture::Texture;
+
+/// How far fog starts from the camera, as a fraction of the view distance.
+const FOG_START: f32 = 0.6;
+
+/// How the sky looks, wherever it's seen from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    /// The color straight up, in linear space.
+    pub zenith: [f32; 3],
+    /// The color at the horizon, which distant terrain fades into.
+    pub horizon: [f32; 3],
+    /// The direction towards the sun. The moon is always opposite.
+    pub sun: [f32; 3],
+    /// How bright the stars are, from 0 (hidden) to 1.
+    pub stars: f32,
+}
+
+impl Default for Sky {
+    /// A black sky, with the sun overhead.
+    fn default() -> Self {
+        Self {
+            zenith: [0.0; 3],
+            horizon: [0.0; 3],
+            sun: [0.0, 1.0, 0.0],
+            stars: 0.0,
+        }
+    }
+}
+
+/// What the sky pass and the fog need to know, laid out for the shaders.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct SkyUniform {
+    /// Transforms clip space back into world space, to find the direction
+    /// each pixel looks in.
+    pub inv_view_proj: [[f32; 4]; 4],
+    pub eye: [f32; 3],
+    pub fog_start: f32,
+    pub sun: [f32; 3],
+    pub fog_end: f32,
+    pub zenith: [f32; 3],
+    pub stars: f32,
+    pub horizon: [f32; 3],
+    pub _padding: f32,
+}
+
+/// Draws the sky behind everything else, and sets how far away terrain fades
+/// into it.
+pub struct SkyPass {
+    pub(super) buffer: wgpu::Buffer,
+    pub(super) pipeline: wgpu::RenderPipeline,
+    pub sky: Sky,
+    /// How far from the camera anything can be seen, in blocks. Terrain is
+    /// lost in fog by this distance, hiding chunks as they load and unload.
+    pub view_distance: f32,
+}
+
+impl SkyPass {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sky Buffer"),
+            size: std::mem::size_of::<SkyUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline = Self::create_pipeline(device, layout, format, shader);
+
+        Self {
+            buffer,
+            pipeline,
+            sky: Sky::default(),
+            view_distance: 256.0,
+        }
+    }
+
+    /// Build the sky's pipeline from `shader`, with `layout` binding the sky
+    /// uniform at group 1.
+    pub(super) fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky render"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // the sky sits at the far plane, and only fills in what the
+            // terrain left empty
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// The color to clear to before drawing, for anything the sky pass
+    /// doesn't cover.
+    pub fn clear_color(&self) -> wgpu::Color {
+        let [r, g, b] = self.sky.horizon.map(f64::from);
+        wgpu::Color { r, g, b, a: 1.0 }
+    }
+
+    pub fn uniform(&self, eye: Vec3<f32>, view_proj: Mat4<f32>) -> SkyUniform {
+        SkyUniform {
+            inv_view_proj: view_proj.inverted().into_col_arrays(),
+            eye: eye.into_array(),
+            fog_start: self.view_distance * FOG_START,
+            sun: Vec3::from(self.sky.sun).normalized().into_array(),
+            fog_end: self.view_distance,
+            zenith: self.sky.zenith,
+            stars: self.sky.stars,
+            horizon: self.sky.horizon,
+            _padding: 0.0,
+        }
+    }
+}
@@ -79,15 +79,20 @@ impl Texture {
         })
     }
 
-    pub fn create_depth_texture(
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        label: &str,
-    ) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, size: [u32; 2], label: &str) -> Self {
+        Self::create_depth(device, size, label)
+    }
+
+    /// Create a square depth texture for rendering shadows into.
+    pub fn create_shadow_map(device: &wgpu::Device, size: u32, label: &str) -> Self {
+        Self::create_depth(device, [size, size], label)
+    }
+
+    fn create_depth(device: &wgpu::Device, [width, height]: [u32; 2], label: &str) -> Self {
         let size = wgpu::Extent3d {
             // 2.
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
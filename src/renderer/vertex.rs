@@ -1,35 +1,133 @@
 use bytemuck::{Pod, Zeroable};
 
-use std::hash;
+use super::mesh::ChunkMesh;
 
+/// A voxel vertex, packed into three words.
+///
+/// The first word holds the position of the vertex relative to its chunk, in
+/// sixteenths of a block and offset by one block so that it can reach just
+/// into the neighbors (10 bits per axis), and its ambient occlusion level (2
+/// bits). The second holds the index of the face's color in the palette (8
+/// bits), the skylight and block light levels reaching it (4 bits each) and
+/// its normal, octahedrally encoded (8 bits per component). The third holds
+/// its texture coordinates, in signed sixteenths of a block (16 bits each).
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Pod, Zeroable)]
 pub struct Vertex {
-    pub position: [f32; 3],
-    pub color: [f32; 4],
-    pub normal: [f32; 3],
-}
-
-// If we have NaN's, we have bigger issues
-impl std::cmp::Eq for Vertex {}
-impl hash::Hash for Vertex {
-    fn hash<H: hash::Hasher>(&self, hasher: &mut H) {
-        for c in self
-            .position
-            .into_iter()
-            .chain(self.color)
-            .chain(self.normal)
-        {
-            hasher.write_u32(c.to_bits());
-        }
-    }
+    pub geometry: u32,
+    pub appearance: u32,
+    pub uv: u32,
 }
 
 pub const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
 
+/// The number of steps each block is divided into along a vertex's axes.
+const SUBDIVISIONS: f32 = 16.0;
+
+/// The normals of blocky faces, indexed by `Direction`.
+const NORMALS: [[f32; 3]; 6] = [
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [-1.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0],
+];
+
+/// The axes a face's texture runs along, and which way, indexed by
+/// `Direction`. Textures are upright on the sides, as seen from outside.
+const UV_AXES: [[(usize, f32); 2]; 6] = [
+    [(0, 1.0), (2, 1.0)],
+    [(0, 1.0), (2, -1.0)],
+    [(2, 1.0), (1, -1.0)],
+    [(2, -1.0), (1, -1.0)],
+    [(0, 1.0), (1, -1.0)],
+    [(0, -1.0), (1, -1.0)],
+];
+
 impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 3] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4, 2 => Float32x3];
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Uint32,
+        1 => Uint32,
+        3 => Uint32,
+    ];
+
+    /// Pack a vertex on the block grid. Each coordinate of `position` must be
+    /// at most 62, `direction` is the `Direction` its face points in, and
+    /// `light` holds the skylight level in its high four bits and the block
+    /// light level in its low four.
+    ///
+    /// The texture is laid over the face one block to a tile.
+    pub fn new(position: [u32; 3], direction: u8, ao: u8, light: u8, color: u32) -> Self {
+        debug_assert!(direction < 6, "invalid direction");
+        Self::pack(
+            position.map(|c| c as f32),
+            NORMALS[direction as usize],
+            direction,
+            ao,
+            light,
+            color,
+        )
+    }
+
+    /// Pack a vertex anywhere from one block below its chunk to 62 blocks
+    /// above, with an arbitrary (normalized) normal and no ambient occlusion.
+    ///
+    /// The texture is projected onto the vertex from whichever side of a
+    /// block its normal is closest to facing.
+    pub fn smooth(position: [f32; 3], normal: [f32; 3], light: u8, color: u32) -> Self {
+        let [x, y, z] = normal;
+        let direction = if y.abs() > 0.7 {
+            if y > 0.0 {
+                0
+            } else {
+                1
+            }
+        } else if x.abs() > z.abs() {
+            if x < 0.0 {
+                2
+            } else {
+                3
+            }
+        } else if z > 0.0 {
+            4
+        } else {
+            5
+        };
+        Self::pack(position, normal, direction, 3, light, color)
+    }
+
+    fn pack(
+        position: [f32; 3],
+        normal: [f32; 3],
+        direction: u8,
+        ao: u8,
+        light: u8,
+        color: u32,
+    ) -> Self {
+        debug_assert!(ao < 4, "invalid ambient occlusion");
+        debug_assert!(color < 1 << 8, "color out of range");
+
+        let [x, y, z] = position.map(|c| {
+            let fixed = ((c + 1.0) * SUBDIVISIONS).round();
+            debug_assert!((0.0..1024.0).contains(&fixed), "vertex out of range");
+            fixed as u32
+        });
+        let [u, v] = encode_normal(normal);
+        let [tu, tv] = UV_AXES[direction as usize]
+            .map(|(axis, sign)| (position[axis] * sign * SUBDIVISIONS).round() as i16 as u16);
+
+        Self {
+            geometry: x | y << 10 | z << 20 | (ao as u32) << 30,
+            appearance: color | (light as u32) << 8 | (u as u32) << 16 | (v as u32) << 24,
+            uv: tu as u32 | (tv as u32) << 16,
+        }
+    }
+
+    /// The position of the vertex relative to its chunk.
+    pub fn position(&self) -> [f32; 3] {
+        [0, 10, 20].map(|shift| (self.geometry >> shift & 1023) as f32 / SUBDIVISIONS - 1.0)
+    }
 
     pub const fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -39,3 +137,164 @@ impl Vertex {
         }
     }
 }
+
+/// Project a unit vector onto an octahedron unfolded into a square, and
+/// quantize it so that each axis direction is represented exactly. Decoded by
+/// `decode_normal` in `vert.wgsl`.
+fn encode_normal([x, y, z]: [f32; 3]) -> [u8; 2] {
+    let l1 = x.abs() + y.abs() + z.abs();
+    let (mut u, mut v) = (x / l1, y / l1);
+    if z < 0.0 {
+        (u, v) = ((1.0 - v.abs()) * u.signum(), (1.0 - u.abs()) * v.signum());
+    }
+    [u, v].map(|c| (c * 127.0).round() as i32 as u8 ^ 0x80)
+}
+
+/// Per-draw data placing a chunk's vertices in the world.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct Instance {
+    pub origin: [f32; 3],
+    /// The [`Mesher`](super::mesh::Mesher) that meshed the chunk, for debug
+    /// views.
+    pub mesher: u32,
+}
+
+impl Instance {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        2 => Float32x3,
+        4 => Uint32,
+    ];
+
+    pub fn new(mesh: &ChunkMesh) -> Self {
+        Self {
+            origin: mesh.origin,
+            mesher: mesh.stats.mesher as u32,
+        }
+    }
+
+    pub const fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A vertex as `vert.wgsl` unpacks it.
+    struct Decoded {
+        position: [f32; 3],
+        ao: u32,
+        uv: [f32; 2],
+        color: u32,
+        sky: u32,
+        block: u32,
+        normal: [f32; 3],
+    }
+
+    /// The same shifts and masks as `vert.wgsl`.
+    fn decode(vertex: Vertex) -> Decoded {
+        let Vertex {
+            geometry,
+            appearance,
+            uv,
+        } = vertex;
+
+        Decoded {
+            position: [0, 10, 20].map(|shift| (geometry >> shift & 1023) as f32 / 16.0 - 1.0),
+            ao: geometry >> 30,
+            uv: [(uv << 16) as i32 >> 16, uv as i32 >> 16].map(|c| c as f32 / 16.0),
+            color: appearance & 255,
+            sky: appearance >> 12 & 15,
+            block: appearance >> 8 & 15,
+            normal: decode_normal(appearance >> 16),
+        }
+    }
+
+    fn decode_normal(encoded: u32) -> [f32; 3] {
+        let [x, y] = [encoded & 255, encoded >> 8].map(|c| (c as f32 - 128.0) / 127.0);
+        let mut n = [x, y, 1.0 - x.abs() - y.abs()];
+        let t = (-n[2]).max(0.0);
+        n[0] += if n[0] >= 0.0 { -t } else { t };
+        n[1] += if n[1] >= 0.0 { -t } else { t };
+        let length = n.iter().map(|c| c * c).sum::<f32>().sqrt();
+        n.map(|c| c / length)
+    }
+
+    fn assert_near(a: [f32; 3], b: [f32; 3], tolerance: f32) {
+        let far = a.iter().zip(b).any(|(a, b)| (a - b).abs() > tolerance);
+        assert!(!far, "{a:?} is not {b:?}");
+    }
+
+    #[test]
+    fn round_trips_block_vertices() {
+        for direction in 0..6 {
+            for (position, ao) in [([0, 0, 0], 0), ([62, 31, 5], 3), ([7, 62, 62], 2)] {
+                let vertex = decode(Vertex::new(position, direction, ao, 0xf3, 200));
+                assert_eq!(vertex.position, position.map(|c| c as f32));
+                assert_eq!(vertex.ao, ao as u32);
+                assert_eq!([vertex.color, vertex.sky, vertex.block], [200, 15, 3]);
+                // axis directions are represented exactly
+                assert_near(vertex.normal, NORMALS[direction as usize], 1e-6);
+
+                let uv =
+                    UV_AXES[direction as usize].map(|(axis, sign)| position[axis] as f32 * sign);
+                assert_eq!(vertex.uv, uv, "direction {direction}");
+            }
+        }
+    }
+
+    #[test]
+    fn sign_extends_texture_coordinates() {
+        // the bottom runs against z, and the sides against y
+        assert_eq!(decode(Vertex::new([3, 5, 9], 1, 3, 0, 0)).uv, [3.0, -9.0]);
+        assert_eq!(decode(Vertex::new([3, 5, 9], 3, 3, 0, 0)).uv, [-9.0, -5.0]);
+        assert_eq!(decode(Vertex::new([3, 5, 9], 0, 3, 0, 0)).uv, [3.0, 9.0]);
+    }
+
+    #[test]
+    fn round_trips_smooth_vertices() {
+        let normals = [
+            [0.3, -0.5, -0.81],
+            [-0.6, 0.7, 0.39],
+            [0.0, -0.2, -0.98],
+            [-0.7, -0.7, 0.1],
+        ];
+        for normal in normals {
+            let length = normal.iter().map(|c: &f32| c * c).sum::<f32>().sqrt();
+            let normal = normal.map(|c| c / length);
+
+            let vertex = decode(Vertex::smooth([-1.0, 62.9375, 0.5], normal, 0x7a, 9));
+            assert_eq!(vertex.position, [-1.0, 62.9375, 0.5]);
+            assert_eq!(vertex.ao, 3);
+            assert_eq!([vertex.color, vertex.sky, vertex.block], [9, 7, 10]);
+            assert_near(vertex.normal, normal, 0.02);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "vertex out of range")]
+    fn rejects_positions_past_the_neighbors() {
+        Vertex::new([63, 0, 0], 0, 3, 0, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "vertex out of range")]
+    fn rejects_positions_below_the_chunk() {
+        Vertex::smooth([0.0, -1.1, 0.0], [0.0, 1.0, 0.0], 0, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "color out of range")]
+    fn rejects_colors_past_the_palette() {
+        Vertex::new([0, 0, 0], 0, 3, 0, 256);
+    }
+}
@@ -3,14 +3,39 @@ struct CameraUniform {
 };
 
 struct Light {
-    position: vec3<f32>,
+    // a position with a w of 1, or a direction towards the light with a w of 0
+    position: vec4<f32>,
     color: vec3<f32>,
+    ambient: f32,
+}
+
+struct Shadow {
+    view_proj: mat4x4<f32>,
+    enabled: u32,
+}
+
+struct PaletteEntry {
+    color: vec4<f32>,
+    // the texture's layer in the atlas
+    layer: u32,
+    // how brightly the material glows by itself, from 0 to 1
+    emission: f32,
 }
 
 struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) color: vec4<f32>,
-    @location(2) normal: vec3<f32>,
+    // x: 10 bits, y: 10 bits, z: 10 bits, ao: 2 bits
+    @location(0) geometry: u32,
+    // palette index: 8 bits, block light: 4 bits, skylight: 4 bits,
+    // octahedral normal: 8 bits per component
+    @location(1) appearance: u32,
+    // texture coordinates: 16 signed bits each, in sixteenths of a block
+    @location(3) uv: u32,
+}
+
+struct InstanceInput {
+    @location(2) origin: vec3<f32>,
+    // the `Mesher` that meshed the chunk
+    @location(4) mesher: u32,
 }
 
 struct VertexOutput {
@@ -18,5 +43,15 @@ struct VertexOutput {
     @location(0) color: vec4<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) ao: f32,
+    // skylight and block light levels, from 0 to 15
+    @location(4) light: vec2<f32>,
+    // in blocks; the texture repeats once per block
+    @location(5) uv: vec2<f32>,
+    @location(6) @interpolate(flat) layer: u32,
+    // the chunk's origin and mesher, for debug views
+    @location(7) @interpolate(flat) chunk: vec3<f32>,
+    @location(8) @interpolate(flat) mesher: u32,
+    @location(9) @interpolate(flat) emission: f32,
 }
 
@@ -0,0 +1,73 @@
+// debug views, each coloring terrain by something other than how it looks
+
+// the color of each `Mesher`, in order: fast, greedy and smooth
+const MESHER_COLORS = array<vec3<f32>, 3>(
+    vec3<f32>(0.9, 0.2, 0.2),
+    vec3<f32>(0.2, 0.8, 0.2),
+    vec3<f32>(0.2, 0.4, 0.9),
+);
+
+// what each fragment adds in the overdraw view, so that a pixel drawn to
+// ten times is fully red, and one drawn to twenty-five times yellow
+const OVERDRAW_HEAT = vec3<f32>(0.1, 0.04, 0.015);
+
+const WIREFRAME_COLOR = vec3<f32>(0.05, 0.05, 0.05);
+
+// the color of skylight in the light level view
+const SKYLIGHT_COLOR = vec3<f32>(0.4, 0.6, 1.0);
+
+// shading from a fixed direction, so that shapes stay readable in flat
+// colors
+fn relief(normal: vec3<f32>) -> f32 {
+    return 0.6 + 0.4 * max(dot(normal, normalize(vec3<f32>(0.3, 1.0, 0.5))), 0.0);
+}
+
+fn hash_u32(x: u32) -> u32 {
+    var h = x * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    return (h >> 22u) ^ h;
+}
+
+// a random color for the chunk at `origin`
+fn chunk_color(origin: vec3<f32>) -> vec3<f32> {
+    let p = bitcast<vec3<u32>>(vec3<i32>(floor(origin)));
+    let h = hash_u32(p.x ^ hash_u32(p.y ^ hash_u32(p.z)));
+    let rgb = vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(255u);
+    return vec3<f32>(rgb) / 255.0;
+}
+
+@fragment
+fn normals(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.world_normal * 0.5 + 0.5, 1.0);
+}
+
+@fragment
+fn chunks(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(chunk_color(in.chunk) * relief(in.world_normal), 1.0);
+}
+
+@fragment
+fn meshers(in: VertexOutput) -> @location(0) vec4<f32> {
+    var colors = MESHER_COLORS;
+    let color = colors[min(in.mesher, 2u)];
+    return vec4<f32>(color * relief(in.world_normal), 1.0);
+}
+
+@fragment
+fn overdraw(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(OVERDRAW_HEAT, 1.0);
+}
+
+// skylight and block light each brighten their own color, linearly with
+// their levels
+@fragment
+fn light_levels(in: VertexOutput) -> @location(0) vec4<f32> {
+    let sky = SKYLIGHT_COLOR * in.light.x / 15.0;
+    let block = BLOCK_LIGHT_COLOR * in.light.y / 15.0;
+    return vec4<f32>(max(sky, block), 1.0);
+}
+
+@fragment
+fn wireframe(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(WIREFRAME_COLOR, 1.0);
+}
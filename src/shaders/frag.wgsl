@@ -1,15 +1,101 @@
+#include "common.wgsl"
+#include "sky.wgsl"
+#include "debug.wgsl"
+
 @group(1) @binding(0)
 var<uniform> light: Light;
 
-@fragment
-fn main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let ambient_strength = 0.2;
-    let ambient_color = vec4<f32>(light.color, 1.0) * ambient_strength;
+@group(2) @binding(1)
+var atlas: texture_2d_array<f32>;
+
+@group(2) @binding(2)
+var atlas_sampler: sampler;
+
+@group(1) @binding(1)
+var<uniform> shadow: Shadow;
+
+@group(1) @binding(2)
+var shadow_map: texture_depth_2d;
+
+@group(1) @binding(3)
+var shadow_sampler: sampler_comparison;
 
-    let light_dir = normalize(light.position - in.world_position);
+// how far along its normal a surface is nudged before looking up its shadow,
+// so that it doesn't shadow itself
+const SHADOW_NORMAL_OFFSET = 0.1;
+
+#ifdef SHADOWS
+// how much of the light reaches a point, from 0 (fully shadowed) to 1,
+// averaged over a 3x3 area of the shadow map to soften the edges
+fn lit(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if shadow.enabled == 0u {
+        return 1.0;
+    }
+
+    let clip = shadow.view_proj * vec4<f32>(world_position + normal * SHADOW_NORMAL_OFFSET, 1.0);
+    let ndc = clip.xyz / clip.w;
+    // anything outside the shadow map is lit
+    if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+
+    var total = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            total += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z);
+        }
+    }
+    return total / 9.0;
+}
+#else
+fn lit(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    return 1.0;
+}
+#endif
 
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = vec4<f32>(light.color, 1.0) * diffuse_strength;
+// the color of light given off by blocks
+const BLOCK_LIGHT_COLOR = vec3<f32>(1.0, 0.85, 0.6);
 
-    return vec4<f32>((ambient_color + diffuse_color) * in.color);
+// how many times brighter than white the most glowing materials are, so that
+// they bloom
+const EMISSION_STRENGTH = 3.0;
+
+// how bright a light level looks; each level is a fixed fraction dimmer than
+// the last, as light appears to the eye
+fn brightness(level: f32) -> f32 {
+    return pow(0.8, 15.0 - level);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    // the texture repeats across merged faces, tinted by the color
+    let albedo = in.color * textureSample(atlas, atlas_sampler, in.uv, in.layer);
+
+    let ambient_color = light.color * light.ambient;
+
+    let light_dir = normalize(light.position.xyz - in.world_position * light.position.w);
+
+    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0)
+        * lit(in.world_position, in.world_normal);
+    let diffuse_color = light.color * diffuse_strength;
+
+    // the sun only reaches as far as the skylight does
+    let sun = (ambient_color + diffuse_color) * brightness(in.light.x);
+    let block = BLOCK_LIGHT_COLOR * brightness(in.light.y);
+
+    let glow = albedo.rgb * in.emission * EMISSION_STRENGTH;
+    let color = max(sun, block) * albedo.rgb * in.ao + glow;
+#ifdef FOG
+    return vec4<f32>(fog(color, in.world_position), albedo.a);
+#else
+    return vec4<f32>(color, albedo.a);
+#endif
+}
+
+@fragment
+fn main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
 }
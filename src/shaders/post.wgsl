@@ -0,0 +1,185 @@
+struct Post {
+    exposure: f32,
+    gamma: f32,
+    bloom_threshold: f32,
+    bloom_strength: f32,
+    // whether the frame has been gamma encoded by the time it's output
+    encoded: u32,
+    // whether the target encodes whatever's written to it as sRGB
+    srgb_target: u32,
+}
+
+// what the pass reads from, which is never what it draws to
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var linear_sampler: sampler;
+
+@group(0) @binding(2)
+var lut: texture_3d<f32>;
+
+@group(0) @binding(3)
+var<uniform> post: Post;
+
+@group(1) @binding(0)
+var bloom_texture: texture_2d<f32>;
+
+struct PostOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// the weights of the center tap of the bloom's blur and each tap out from it
+const BLUR_WEIGHTS = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+// how strongly FXAA follows the edges it finds, and how far along them it
+// looks, in texels
+const FXAA_REDUCE_MIN = 0.0078125;
+const FXAA_REDUCE_MUL = 0.125;
+const FXAA_SPAN_MAX = 8.0;
+
+// a triangle covering the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> PostOutput {
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+
+    var out: PostOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = ndc * vec2<f32>(0.5, -0.5) + 0.5;
+    return out;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let curve = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(curve, color * 12.92, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let curve = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(curve, color / 12.92, color <= vec3<f32>(0.04045));
+}
+
+fn sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(source, linear_sampler, uv).rgb;
+}
+
+// keep only what's brighter than the threshold, averaging the frame down to
+// the bloom's size; each bilinear tap covers 2x2 texels
+@fragment
+fn bloom_extract(in: PostOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let color = (sample(in.uv + texel * vec2<f32>(-1.0, -1.0))
+        + sample(in.uv + texel * vec2<f32>(1.0, -1.0))
+        + sample(in.uv + texel * vec2<f32>(-1.0, 1.0))
+        + sample(in.uv + texel * vec2<f32>(1.0, 1.0))) * 0.25;
+
+    // scale rather than subtract, so that bright colors keep their hue
+    let brightness = luma(color);
+    let excess = max(brightness - post.bloom_threshold, 0.0);
+    return vec4<f32>(color * excess / max(brightness, 0.0001), 1.0);
+}
+
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    var weights = BLUR_WEIGHTS;
+    let texel = direction / vec2<f32>(textureDimensions(source));
+
+    var color = sample(uv) * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = texel * f32(i);
+        color += (sample(uv + offset) + sample(uv - offset)) * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn blur_horizontal(in: PostOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn blur_vertical(in: PostOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+@fragment
+fn bloom(in: PostOutput) -> @location(0) vec4<f32> {
+    let glow = textureSample(bloom_texture, linear_sampler, in.uv).rgb;
+    return vec4<f32>(sample(in.uv) + glow * post.bloom_strength, 1.0);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+@fragment
+fn tone_mapping(in: PostOutput) -> @location(0) vec4<f32> {
+    let x = sample(in.uv) * post.exposure;
+    let mapped = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+    return vec4<f32>(clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+
+// look each color up by its sRGB encoding, which is how tables are made
+@fragment
+fn color_grading(in: PostOutput) -> @location(0) vec4<f32> {
+    let encoded = linear_to_srgb(clamp(sample(in.uv), vec3<f32>(0.0), vec3<f32>(1.0)));
+    // the centers of the first and last texels are the ends of the range
+    let size = f32(textureDimensions(lut).x);
+    let uvw = encoded * (size - 1.0) / size + 0.5 / size;
+    let graded = textureSample(lut, linear_sampler, uvw).rgb;
+    return vec4<f32>(srgb_to_linear(graded), 1.0);
+}
+
+@fragment
+fn gamma(in: PostOutput) -> @location(0) vec4<f32> {
+    let color = max(sample(in.uv), vec3<f32>(0.0));
+    return vec4<f32>(pow(color, vec3<f32>(1.0 / post.gamma)), 1.0);
+}
+
+// edges are found by brightness as it looks, which is roughly the square
+// root of its linear value
+fn fxaa_luma(color: vec3<f32>) -> f32 {
+    let value = luma(color);
+    return select(sqrt(max(value, 0.0)), value, post.encoded != 0u);
+}
+
+// Timothy Lottes' fast approximate anti-aliasing, in its simplest form:
+// blur along whichever way the brightness changes least, unless that
+// overshoots the neighborhood
+@fragment
+fn fxaa(in: PostOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let nw = fxaa_luma(sample(in.uv + texel * vec2<f32>(-1.0, -1.0)));
+    let ne = fxaa_luma(sample(in.uv + texel * vec2<f32>(1.0, -1.0)));
+    let sw = fxaa_luma(sample(in.uv + texel * vec2<f32>(-1.0, 1.0)));
+    let se = fxaa_luma(sample(in.uv + texel * vec2<f32>(1.0, 1.0)));
+    let m = fxaa_luma(sample(in.uv));
+    let lowest = min(m, min(min(nw, ne), min(sw, se)));
+    let highest = max(m, max(max(nw, ne), max(sw, se)));
+
+    var direction = vec2<f32>((sw + se) - (nw + ne), (nw + sw) - (ne + se));
+    let reduce = max((nw + ne + sw + se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let near = 0.5 * (sample(in.uv - direction / 6.0) + sample(in.uv + direction / 6.0));
+    let far = near * 0.5 + 0.25 * (sample(in.uv - direction * 0.5) + sample(in.uv + direction * 0.5));
+    let far_luma = fxaa_luma(far);
+    if far_luma < lowest || far_luma > highest {
+        return vec4<f32>(near, 1.0);
+    }
+    return vec4<f32>(far, 1.0);
+}
+
+// copy the frame to the target, which may encode it as sRGB itself
+@fragment
+fn output(in: PostOutput) -> @location(0) vec4<f32> {
+    var color = textureLoad(source, vec2<i32>(in.clip_position.xy), 0).rgb;
+    if post.encoded != 0u && post.srgb_target != 0u {
+        color = srgb_to_linear(color);
+    } else if post.encoded == 0u && post.srgb_target == 0u {
+        color = linear_to_srgb(max(color, vec3<f32>(0.0)));
+    }
+    return vec4<f32>(color, 1.0);
+}
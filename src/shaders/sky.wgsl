@@ -0,0 +1,34 @@
+struct Sky {
+    inv_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    fog_start: f32,
+    // the direction towards the sun; the moon is always opposite
+    sun: vec3<f32>,
+    fog_end: f32,
+    zenith: vec3<f32>,
+    stars: f32,
+    horizon: vec3<f32>,
+}
+
+@group(1) @binding(4)
+var<uniform> sky: Sky;
+
+// the color of the sky looking in `direction`, without the sun, moon or
+// stars; anything below the horizon takes the horizon's color
+fn sky_color(direction: vec3<f32>) -> vec3<f32> {
+    let height = clamp(direction.y, 0.0, 1.0);
+    let color = mix(sky.horizon, sky.zenith, sqrt(height));
+
+    // the sky glows a little around the sun
+    let towards_sun = max(dot(direction, sky.sun), 0.0);
+    return color + sky.horizon * pow(towards_sun, 8.0) * 0.3;
+}
+
+// fade a surface at `position` into the sky behind it, so that it's lost
+// entirely by the end of the fog
+fn fog(color: vec3<f32>, position: vec3<f32>) -> vec3<f32> {
+    let offset = position - sky.eye;
+    let distance = max(length(offset), 0.0001);
+    let amount = smoothstep(sky.fog_start, sky.fog_end, distance);
+    return mix(color, sky_color(offset / distance), amount);
+}
@@ -0,0 +1,66 @@
+#include "common.wgsl"
+#include "sky.wgsl"
+
+struct SkyOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// the cosines of the angles the sun and moon cover from their centers
+const SUN_SIZE = 0.9994;
+const MOON_SIZE = 0.9996;
+const SUN_COLOR = vec3<f32>(1.0, 0.95, 0.8);
+const MOON_COLOR = vec3<f32>(0.6, 0.65, 0.75);
+
+// how many cells each axis of the sky is divided into, each of which may
+// hold a star
+const STAR_GRID = 120.0;
+// the fraction of cells without a star
+const STAR_RARITY = 0.997;
+
+// a triangle covering the whole screen, at the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> SkyOutput {
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+
+    var out: SkyOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn hash(p: vec3<f32>) -> f32 {
+    return fract(sin(dot(p, vec3<f32>(12.9898, 78.233, 45.164))) * 43758.5453);
+}
+
+// how bright the star looking in `direction` is, if there is one
+fn star(direction: vec3<f32>) -> f32 {
+    let cell = floor(direction * STAR_GRID);
+    let h = hash(cell);
+    if h < STAR_RARITY {
+        return 0.0;
+    }
+
+    let brightness = (h - STAR_RARITY) / (1.0 - STAR_RARITY);
+    let from_center = length(fract(direction * STAR_GRID) - 0.5);
+    return brightness * max(1.0 - 2.0 * from_center, 0.0);
+}
+
+// a disc with a slightly soft edge
+fn disc(direction: vec3<f32>, center: vec3<f32>, size: f32) -> f32 {
+    return smoothstep(size - 0.0002, size, dot(direction, center));
+}
+
+@fragment
+fn fs_main(in: SkyOutput) -> @location(0) vec4<f32> {
+    let far = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w - sky.eye);
+
+    // the horizon hides anything below it
+    let above = smoothstep(-0.05, 0.0, direction.y);
+    let lights = SUN_COLOR * disc(direction, sky.sun, SUN_SIZE)
+        + MOON_COLOR * disc(direction, -sky.sun, MOON_SIZE)
+        + vec3<f32>(star(direction) * sky.stars);
+
+    return vec4<f32>(sky_color(direction) + lights * above, 1.0);
+}
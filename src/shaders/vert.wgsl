@@ -1,14 +1,57 @@
+#include "common.wgsl"
+
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+@group(2) @binding(0)
+var<storage, read> palette: array<PaletteEntry>;
+
+// the inverse of `encode_normal` in `vertex.rs`
+fn decode_normal(encoded: u32) -> vec3<f32> {
+    let e = (vec2<f32>(f32(encoded & 255u), f32(encoded >> 8u)) - 128.0) / 127.0;
+    var n = vec3<f32>(e, 1.0 - abs(e.x) - abs(e.y));
+    let t = max(-n.z, 0.0);
+    n.x += select(t, -t, n.x >= 0.0);
+    n.y += select(t, -t, n.y >= 0.0);
+    return normalize(n);
+}
+
 @vertex
 fn main(
     model: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
+    // the brightness of each ambient occlusion level, from fully occluded to
+    // unoccluded
+    var ao_curve = array<f32, 4>(0.45, 0.6, 0.8, 1.0);
+
+    // positions are in sixteenths of a block, offset by one block
+    let position = vec3<f32>(
+        f32(model.geometry & 1023u),
+        f32((model.geometry >> 10u) & 1023u),
+        f32((model.geometry >> 20u) & 1023u),
+    ) / 16.0 - 1.0 + instance.origin;
+    let ao = model.geometry >> 30u;
+
+    // sign extend each half of the word
+    let uv = vec2<i32>(bitcast<i32>(model.uv << 16u), bitcast<i32>(model.uv)) >> vec2<u32>(16u);
+
+    let entry = palette[model.appearance & 255u];
+
     var out: VertexOutput;
-    out.color = model.color;
-    out.world_normal = model.normal;
-    out.world_position = model.position;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.color = entry.color;
+    out.uv = vec2<f32>(uv) / 16.0;
+    out.layer = entry.layer;
+    out.emission = entry.emission;
+    out.world_normal = decode_normal(model.appearance >> 16u);
+    out.world_position = position;
+    out.chunk = instance.origin;
+    out.mesher = instance.mesher;
+    out.ao = ao_curve[ao];
+    out.light = vec2<f32>(
+        f32((model.appearance >> 12u) & 15u),
+        f32((model.appearance >> 8u) & 15u),
+    );
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
     return out;
 }
@@ -0,0 +1,107 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use image::RgbaImage;
+use voxers::app::{self, Clock, Terrain};
+use voxers::renderer::camera::Camera;
+use voxers::renderer::post::PostSettings;
+use voxers::renderer::Renderer;
+use winit::dpi::PhysicalSize;
+
+/// How far apart each channel of a pixel can be before it's counted as
+/// different from the golden image.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// The fraction of pixels that can differ from the golden image, for the
+/// odd edge rasterized differently.
+const PIXEL_TOLERANCE: f64 = 0.01;
+
+/// Compare `image` against `tests/golden/{name}.png`, within tolerance.
+///
+/// Set `UPDATE_GOLDEN=1` to write `image` there instead, after checking that
+/// it looks right.
+fn assert_matches_golden(image: &RgbaImage, name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension("png");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image.save(&path).unwrap();
+        return;
+    }
+
+    let golden = image::open(&path)
+        .unwrap_or_else(|err| panic!("loading {path:?}: {err}"))
+        .to_rgba8();
+    assert_eq!(image.dimensions(), golden.dimensions());
+
+    let differing = image
+        .pixels()
+        .zip(golden.pixels())
+        .filter(|(a, b)| (0..4).any(|i| a[i].abs_diff(b[i]) > CHANNEL_TOLERANCE))
+        .count();
+    let fraction = differing as f64 / (image.width() * image.height()) as f64;
+    assert!(
+        fraction <= PIXEL_TOLERANCE,
+        "{:.1}% of pixels differ from {path:?}",
+        fraction * 100.0
+    );
+}
+
+/// Render the world from one corner, with the sky above it, after passing
+/// the renderer to `setup`.
+fn render_world(setup: impl FnOnce(&mut Renderer)) -> RgbaImage {
+    let size = PhysicalSize::new(160, 120);
+    // a software adapter, so that every machine can run the test and draws
+    // much the same image
+    let mut renderer =
+        pollster::block_on(Renderer::offscreen(size, true)).expect("no adapter to render with");
+    setup(&mut renderer);
+
+    let mut clock = Clock::default();
+    clock.set_time(0.45);
+    renderer.light(clock.light());
+    renderer.set_sky(clock.sky());
+
+    let chunks = app::mesh_world(&mut renderer, 0, [2, 1, 2], Terrain::Blocky);
+    assert_eq!(chunks.len(), 4);
+
+    let mut camera = Camera::new([-8.0, 48.0, -8.0], 160.0 / 120.0);
+    camera.target = [32.0, 24.0, 32.0].into();
+    camera.fovy = 1.2;
+    renderer.update_camera(&camera);
+
+    let image = renderer.render_to_image(&chunks).unwrap();
+    assert_eq!(image.dimensions(), (160, 120));
+    image
+}
+
+#[test]
+fn renders_offscreen() {
+    let image = render_world(|_| {});
+
+    // exact colors depend on the adapter, so only check which dominates
+    for (x, y) in [(5, 5), (155, 5), (80, 10)] {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        assert!(b > g && g > r && a == 255, "({x}, {y}) isn't sky");
+    }
+    for (x, y) in [(80, 75), (60, 70), (100, 70)] {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        assert!(g > r && g > b && a == 255, "({x}, {y}) isn't grass");
+    }
+
+    assert_matches_golden(&image, "world");
+}
+
+#[test]
+fn renders_plainly_without_post_effects() {
+    let image = render_world(|renderer| {
+        let settings = PostSettings {
+            effects: BTreeSet::new(),
+            ..renderer.post_settings().clone()
+        };
+        renderer.set_post_settings(settings);
+    });
+
+    // rendered before there was any post-processing
+    assert_matches_golden(&image, "world_plain");
+}